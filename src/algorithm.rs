@@ -0,0 +1,56 @@
+//! Operator routing (DX-style algorithms)
+
+use crate::NUM_OPERATORS;
+
+/// How operators are connected: which operators modulate each one, and
+/// which are mixed to the output. Operators are numbered from 0 (op 1).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Algorithm {
+    /// Routing diagram, e.g. "4>3>2>1"
+    pub name: &'static str,
+    /// For each operator, a bitmask of the operators modulating it. Only
+    /// higher-numbered operators may modulate lower-numbered ones.
+    pub modulators: [u8; NUM_OPERATORS],
+    /// Bitmask of operators heard at the output
+    pub carriers: u8,
+}
+
+/// The eight classic four-operator algorithms (DX21/DX100/TX81Z numbering)
+pub const ALGORITHMS: [Algorithm; 8] = [
+    // Serial stack
+    Algorithm { name: "4>3>2>1", modulators: [0b0010, 0b0100, 0b1000, 0], carriers: 0b0001 },
+    // 3 and 4 both drive 2
+    Algorithm { name: "(3+4)>2>1", modulators: [0b0010, 0b1100, 0, 0], carriers: 0b0001 },
+    // 3>2 and 4 both drive the carrier
+    Algorithm { name: "(3>2 + 4)>1", modulators: [0b1010, 0b0100, 0, 0], carriers: 0b0001 },
+    // 2 and 4>3 both drive the carrier
+    Algorithm { name: "(2 + 4>3)>1", modulators: [0b0110, 0, 0b1000, 0], carriers: 0b0001 },
+    // Two parallel 2-op stacks
+    Algorithm { name: "2>1 + 4>3", modulators: [0b0010, 0, 0b1000, 0], carriers: 0b0101 },
+    // 4 drives three carriers
+    Algorithm { name: "4>(1+2+3)", modulators: [0b1000, 0b1000, 0b1000, 0], carriers: 0b0111 },
+    // One 2-op stack plus two plain sines
+    Algorithm { name: "1 + 2 + 4>3", modulators: [0, 0, 0b1000, 0], carriers: 0b0111 },
+    // Additive: every operator is heard
+    Algorithm { name: "1+2+3+4", modulators: [0, 0, 0, 0], carriers: 0b1111 },
+];
+
+impl Algorithm {
+    /// Look up a standard algorithm by its 1-based number
+    pub fn get(number: usize) -> Option<&'static Algorithm> {
+        ALGORITHMS.get(number.checked_sub(1)?)
+    }
+
+    /// True if operator `from` modulates operator `to`
+    pub fn modulates(&self, from: usize, to: usize) -> bool {
+        self.modulators[to] & (1 << from) != 0
+    }
+
+    pub fn is_carrier(&self, op: usize) -> bool {
+        self.carriers & (1 << op) != 0
+    }
+
+    pub fn carrier_count(&self) -> u32 {
+        self.carriers.count_ones()
+    }
+}
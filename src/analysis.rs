@@ -0,0 +1,147 @@
+//! Level and spectrum measurements for comparing renders, and predicted
+//! FM spectra
+
+use std::f32::consts::PI;
+
+/// Frame length of the spectrum analysis, in samples
+const FRAME_LEN: usize = 4096;
+
+/// Centre of the lowest octave band in Hz, five octaves below 1 kHz
+const FIRST_BAND_CENTRE: f32 = 31.25;
+
+/// Largest absolute sample value
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak, &s| peak.max(s.abs()))
+}
+
+/// Root-mean-square level
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|&s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Linear gain to decibels, with silence floored at -120 dB
+pub fn to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1e-6).log10()
+}
+
+/// Average power in octave bands up to Nyquist, as (centre Hz, dB) pairs
+pub fn octave_bands(samples: &[f32], sample_rate: f32) -> Vec<(f32, f32)> {
+    let spectrum = power_spectrum(samples);
+    let bin_hz = sample_rate / FRAME_LEN as f32;
+
+    let mut bands = Vec::new();
+    let mut centre = FIRST_BAND_CENTRE;
+    while centre * 2.0_f32.sqrt() <= sample_rate / 2.0 {
+        let (low, high) = (centre / 2.0_f32.sqrt(), centre * 2.0_f32.sqrt());
+        let bins = (low / bin_hz).ceil() as usize..(high / bin_hz).ceil() as usize;
+        let power: f32 = spectrum[bins].iter().sum();
+        bands.push((centre, 10.0 * power.max(1e-12).log10()));
+        centre *= 2.0;
+    }
+    bands
+}
+
+/// Sidebands beyond the index included by [`sidebands`]; past this the
+/// Bessel functions are negligible
+const EXTRA_SIDEBANDS: usize = 6;
+
+/// Predicted spectrum of a sine at `carrier` phase-modulated by a sine at
+/// `modulator` (in Hz, or as ratios of the note) with a peak deviation of
+/// `index` radians. Returns (frequency, amplitude) pairs, lowest first,
+/// with amplitudes relative to the unmodulated carrier. Sidebands below
+/// zero frequency fold back onto the positive side, adding to or
+/// cancelling what is already there.
+pub fn sidebands(carrier: f32, modulator: f32, index: f32) -> Vec<(f32, f32)> {
+    let orders = index.abs().ceil() as i32 + EXTRA_SIDEBANDS as i32;
+    let mut components: Vec<(f32, f32)> = Vec::new();
+    for n in -orders..=orders {
+        // sin(-x) = -sin(x), so a folded component flips sign
+        let frequency = carrier + n as f32 * modulator;
+        let amplitude = bessel_j(n, index) * frequency.signum();
+        let frequency = frequency.abs();
+        
+        let tolerance = 1e-4 * carrier.abs().max(modulator.abs());
+        match components.iter_mut().find(|(f, _)| (f - frequency).abs() <= tolerance) {
+            Some((_, a)) => *a += amplitude,
+            None => components.push((frequency, amplitude)),
+        }
+    }
+    
+    components.sort_by(|a, b| a.0.total_cmp(&b.0));
+    components.into_iter().map(|(f, a)| (f, a.abs())).collect()
+}
+
+/// Bessel function of the first kind J_n(x), from its integral form. The
+/// integrand is periodic, so the trapezoid rule converges very quickly.
+fn bessel_j(n: i32, x: f32) -> f32 {
+    let (n, x) = (n as f64, x as f64);
+    let steps = 64 + 4 * x.abs().ceil() as usize;
+    let sum: f64 = (0..steps)
+        .map(|i| {
+            let tau = std::f64::consts::PI * (i as f64 + 0.5) / steps as f64;
+            (n * tau - x * tau.sin()).cos()
+        })
+        .sum();
+    (sum / steps as f64) as f32
+}
+
+/// Hann-windowed power per FFT bin, averaged over consecutive frames
+fn power_spectrum(samples: &[f32]) -> Vec<f32> {
+    let mut power = vec![0.0; FRAME_LEN / 2];
+    let frames = samples.chunks_exact(FRAME_LEN);
+    let count = frames.len().max(1) as f32;
+
+    for frame in frames {
+        let mut re: Vec<f32> = frame.iter()
+            .enumerate()
+            .map(|(i, &s)| s * (0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME_LEN as f32).cos()))
+            .collect();
+        let mut im = vec![0.0; FRAME_LEN];
+        fft(&mut re, &mut im);
+        for (bin, p) in power.iter_mut().enumerate() {
+            *p += (re[bin] * re[bin] + im[bin] * im[bin]) / count;
+        }
+    }
+    power
+}
+
+/// In-place radix-2 FFT; the length must be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
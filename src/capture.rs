@@ -0,0 +1,47 @@
+//! Rolling capture of recent output
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Ring buffer holding the last few seconds of output, so a take can be
+/// saved after the fact. It is lock-free: the audio thread pushes while
+/// another thread copies out, without either waiting.
+pub struct CaptureBuffer {
+    samples: Vec<AtomicU32>,  // f32 bits
+    written: AtomicUsize,     // Samples pushed so far
+    sample_rate: f32,
+}
+
+impl CaptureBuffer {
+    /// Create a buffer keeping the most recent `seconds` of audio
+    pub fn new(sample_rate: f32, seconds: f32) -> Self {
+        let len = ((sample_rate * seconds) as usize).max(1);
+        Self {
+            samples: (0..len).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            sample_rate,
+        }
+    }
+
+    /// Append a sample. Only one thread may push.
+    pub fn push(&self, sample: f32) {
+        let written = self.written.load(Ordering::Relaxed);
+        self.samples[written % self.samples.len()].store(sample.to_bits(), Ordering::Relaxed);
+        self.written.store(written + 1, Ordering::Release);
+    }
+
+    /// Copy out up to the last `seconds` of audio, oldest sample first.
+    /// Samples pushed meanwhile may overwrite the oldest few.
+    pub fn last(&self, seconds: f32) -> Vec<f32> {
+        let written = self.written.load(Ordering::Acquire);
+        let available = written.min(self.samples.len());
+        let count = ((self.sample_rate * seconds) as usize).min(available);
+        
+        (written - count..written)
+            .map(|i| f32::from_bits(self.samples[i % self.samples.len()].load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+}
@@ -0,0 +1,324 @@
+//! Lock-free control of a synth owned by the audio thread
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::sync::{Arc, Mutex};
+
+use crate::{EventLog, FMParams, FMSynth, KeyedTuning, LoggedEvent, MidiMessage, ParamError, Tuning};
+
+/// A change requested from a control thread, applied by [`FMSynth::apply`]
+pub enum Command {
+    NoteOn { note: u8, velocity: f32 },
+    NoteOff { note: u8 },
+    AllNotesOff,
+    /// Bend position from -1.0 to 1.0
+    PitchBend(f32),
+    /// Mod wheel position from 0.0 to 1.0
+    ModWheel(f32),
+    Midi(MidiMessage),
+    /// A patch built and validated by [`Controller::set_params`], swapped
+    /// in as one pointer
+    SetParams(Arc<FMParams>),
+    /// A patch switch, crossfaded by [`FMSynth::switch_params`]; already
+    /// validated by [`Controller::switch_params`]
+    SwitchParams(Arc<FMParams>),
+    SetTuning(Box<dyn Tuning>),
+}
+
+/// Heap data a command displaced on the audio thread. The queue sends it
+/// back to the controllers, which free it when they next send, so the
+/// audio thread never deallocates.
+pub enum Retired {
+    Params(Arc<FMParams>),
+    Tuning(Box<dyn Tuning>),
+}
+
+/// A command and the output sample it is due at; 0 for at once
+struct Timed {
+    at: u64,
+    command: Command,
+}
+
+/// Create a linked [`Controller`] and [`CommandQueue`] holding up to
+/// `capacity` pending commands, and as many scheduled ones
+pub fn control_channel(capacity: usize) -> (Controller, CommandQueue) {
+    let (sender, receiver) = sync_channel(capacity.max(1));
+    // Each command displaces at most one thing, and controllers collect
+    // them before every send, so this never fills with what's in flight
+    let (retire, retired) = sync_channel(2 * capacity.max(1) + 1);
+    let clock = Arc::new(AtomicU64::new(0));
+    let controller = Controller {
+        sender,
+        retired: Arc::new(Mutex::new(retired)),
+        clock: Arc::clone(&clock),
+        log: Arc::new(Mutex::new(None)),
+        tuning: Arc::new(Mutex::new(Some(KeyedTuning::default()))),
+    };
+    let queue = CommandQueue {
+        receiver,
+        retire,
+        clock,
+        scheduled: Vec::with_capacity(capacity.max(1)),
+    };
+    (controller, queue)
+}
+
+/// Sending half, used from control threads (UI, MIDI input). Sends only
+/// block if the audio thread has fallen a whole queue behind. Clones share
+/// one event log and the record of the tuning last sent.
+#[derive(Clone)]
+pub struct Controller {
+    sender: SyncSender<Timed>,
+    retired: Arc<Mutex<Receiver<Retired>>>,    // Freed here rather than on the audio thread
+    clock: Arc<AtomicU64>,                     // Samples rendered, advanced by the queue
+    log: Arc<Mutex<Option<(EventLog, u64)>>>,  // With its start position; only ever locked by control threads
+    tuning: Arc<Mutex<Option<KeyedTuning>>>,   // Last sent, if keyed
+}
+
+// The performance commands aren't validated here, since they are sent as
+// they are played and there's no caller to report to mid-phrase. The synth
+// sanitizes them instead: out-of-range amounts are clamped and non-finite
+// ones ignored, as documented on its methods.
+impl Controller {
+    /// Start `note` at `velocity`, clamped to 0.0 - 1.0. A non-finite
+    /// velocity drops the note.
+    pub fn note_on(&self, note: u8, velocity: f32) {
+        self.send(Command::NoteOn { note, velocity });
+    }
+
+    pub fn note_off(&self, note: u8) {
+        self.send(Command::NoteOff { note });
+    }
+
+    pub fn all_notes_off(&self) {
+        self.send(Command::AllNotesOff);
+    }
+
+    /// Bend every voice, `amount` clamped to -1.0 - 1.0; a non-finite
+    /// amount is ignored
+    pub fn pitch_bend(&self, amount: f32) {
+        self.send(Command::PitchBend(amount));
+    }
+
+    /// Set the mod wheel, `amount` clamped to 0.0 - 1.0; a non-finite
+    /// amount is ignored
+    pub fn mod_wheel(&self, amount: f32) {
+        self.send(Command::ModWheel(amount));
+    }
+
+    pub fn handle_midi(&self, message: MidiMessage) {
+        self.send(Command::Midi(message));
+    }
+
+    /// Validate `params` here, so a bad value is reported to the caller
+    /// rather than dropped on the audio thread, and build the shared patch
+    /// the synth swaps in
+    pub fn set_params(&self, params: FMParams) -> Result<(), ParamError> {
+        params.validate()?;
+        self.send(Command::SetParams(Arc::new(params)));
+        Ok(())
+    }
+
+    /// Validate `params` like [`set_params`](Self::set_params), then
+    /// switch to them with the synth's crossfade
+    pub fn switch_params(&self, params: FMParams) -> Result<(), ParamError> {
+        params.validate()?;
+        self.send(Command::SwitchParams(Arc::new(params)));
+        Ok(())
+    }
+
+    pub fn set_tuning(&self, tuning: impl Tuning + 'static) {
+        self.send(Command::SetTuning(Box::new(tuning)));
+    }
+
+    /// The tuning last sent from any clone, starting from equal
+    /// temperament, or `None` if it isn't a [`KeyedTuning`]
+    pub fn tuning(&self) -> Option<KeyedTuning> {
+        *self.tuning.lock().unwrap()
+    }
+
+    /// Send `command` to be applied at the start of the next block
+    pub fn send(&self, command: Command) {
+        self.schedule(0, command);
+    }
+
+    /// Send `command` to be applied exactly at output sample `at`, as
+    /// counted by [`position`](Self::position), if the queue renders
+    /// with [`CommandQueue::render`]. Commands due in the past apply at
+    /// the start of the next block.
+    pub fn schedule(&self, at: u64, command: Command) {
+        self.retired.lock().unwrap().try_iter().for_each(drop);
+        if let Command::SetTuning(tuning) = &command {
+            *self.tuning.lock().unwrap() = tuning.keyed();
+        }
+        if let Some((log, start)) = self.log.lock().unwrap().as_mut() {
+            // Unscheduled commands apply within a block of here
+            if let Some(event) = LoggedEvent::from_command(&command) {
+                let _ = log.write(at.max(self.position()) - *start, event);
+            }
+        }
+
+        // Fails only once the queue is dropped, when there is nothing left
+        // to control
+        let _ = self.sender.send(Timed { at, command });
+    }
+
+    /// Output samples rendered so far, as reported by
+    /// [`CommandQueue::advance`]
+    pub fn position(&self) -> u64 {
+        self.clock.load(Ordering::Relaxed)
+    }
+
+    /// Record every command sent from now on, from any clone, to `log`,
+    /// timestamped from the current position. Write errors are ignored so
+    /// logging can't interrupt playing.
+    pub fn start_log(&self, log: EventLog) {
+        *self.log.lock().unwrap() = Some((log, self.position()));
+    }
+
+    pub fn stop_log(&self) {
+        *self.log.lock().unwrap() = None;
+    }
+}
+
+/// Receiving half, owned by the audio thread alongside the synth
+pub struct CommandQueue {
+    receiver: Receiver<Timed>,
+    retire: SyncSender<Retired>,
+    clock: Arc<AtomicU64>,
+    scheduled: Vec<Timed>,  // Commands not yet due, soonest first
+}
+
+impl CommandQueue {
+    /// Apply every pending command that is due to `synth` without
+    /// blocking, keeping scheduled ones for later
+    pub fn apply(&mut self, synth: &mut FMSynth) {
+        let position = self.clock.load(Ordering::Relaxed);
+        while let Ok(timed) = self.receiver.try_recv() {
+            // With no room left to hold it, a command plays early rather
+            // than allocating on the audio thread
+            if timed.at <= position || self.scheduled.len() == self.scheduled.capacity() {
+                self.apply_now(synth, timed.command);
+            } else {
+                let index = self.scheduled.partition_point(|pending| pending.at <= timed.at);
+                self.scheduled.insert(index, timed);
+            }
+        }
+        self.apply_due(synth, position);
+    }
+
+    /// Render a block through `render`, applying pending commands first
+    /// and scheduled ones on their exact samples by splitting the block
+    /// around them, then count the block with [`advance`](Self::advance).
+    /// `left` and `right` must be the same length.
+    pub fn render(
+        &mut self,
+        synth: &mut FMSynth,
+        left: &mut [f32],
+        right: &mut [f32],
+        mut render: impl FnMut(&mut FMSynth, &mut [f32], &mut [f32]),
+    ) {
+        assert_eq!(left.len(), right.len(), "channels differ in length");
+        self.apply(synth);
+        let position = self.clock.load(Ordering::Relaxed);
+        let mut start = 0;
+        while start < left.len() {
+            self.apply_due(synth, position + start as u64);
+            let end = self.scheduled.first()
+                .map_or(left.len(), |next| (next.at - position) as usize)
+                .min(left.len());
+            render(synth, &mut left[start..end], &mut right[start..end]);
+            start = end;
+        }
+        self.advance(left.len());
+    }
+
+    /// Count `frames` more output samples, timestamping later log entries
+    pub fn advance(&self, frames: usize) {
+        self.clock.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    /// Apply scheduled commands due by output sample `position`
+    fn apply_due(&mut self, synth: &mut FMSynth, position: u64) {
+        let due = self.scheduled.partition_point(|pending| pending.at <= position);
+        for timed in self.scheduled.drain(..due) {
+            if let Some(retired) = synth.apply(timed.command) {
+                let _ = self.retire.try_send(retired);
+            }
+        }
+    }
+
+    /// Apply `command`, sending back what it displaced to be freed
+    fn apply_now(&self, synth: &mut FMSynth, command: Command) {
+        if let Some(retired) = synth.apply(command) {
+            let _ = self.retire.try_send(retired);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyedTuning;
+
+    #[test]
+    fn displaced_patches_are_freed_by_the_controller() {
+        let (controller, mut queue) = control_channel(16);
+        let mut synth = FMSynth::new(48000.0, FMParams::default()).unwrap();
+        let first = Arc::new(FMParams::default());
+        controller.send(Command::SetParams(Arc::clone(&first)));
+        queue.apply(&mut synth);
+        assert_eq!(Arc::strong_count(&first), 2);
+
+        // The synth hands the patch back rather than dropping it
+        controller.send(Command::SetParams(Arc::new(FMParams::default())));
+        queue.apply(&mut synth);
+        assert_eq!(Arc::strong_count(&first), 2);
+        controller.all_notes_off();
+        assert_eq!(Arc::strong_count(&first), 1);
+    }
+
+    #[test]
+    fn replaced_tunings_are_handed_back() {
+        let mut synth = FMSynth::new(48000.0, FMParams::default()).unwrap();
+        let retired = synth.apply(Command::SetTuning(Box::new(KeyedTuning::default())));
+        assert!(matches!(retired, Some(Retired::Tuning(_))));
+    }
+
+    #[test]
+    fn scheduled_notes_start_on_their_sample() {
+        // The first sample a note renders, counted from its note-on
+        let mut synth = FMSynth::new(48000.0, FMParams::default()).unwrap();
+        synth.note_on(69, 1.0);
+        let (mut left, mut right) = ([0.0; 256], [0.0; 256]);
+        synth.process_stereo(&mut left, &mut right);
+        let onset = left.iter().position(|&sample| sample != 0.0).unwrap();
+
+        let (controller, mut queue) = control_channel(16);
+        let mut synth = FMSynth::new(48000.0, FMParams::default()).unwrap();
+        controller.schedule(100, Command::NoteOn { note: 69, velocity: 1.0 });
+        let (mut left, mut right) = ([0.0; 256], [0.0; 256]);
+        queue.render(&mut synth, &mut left, &mut right, |synth, l, r| synth.process_stereo(l, r));
+        assert_eq!(left.iter().position(|&sample| sample != 0.0), Some(100 + onset));
+        assert_eq!(controller.position(), 256);
+    }
+
+    #[test]
+    fn clones_see_the_tuning_last_set() {
+        struct Stretched;
+        impl Tuning for Stretched {
+            fn frequency(&self, note: u8) -> f32 {
+                crate::note_to_freq(note) * 1.01
+            }
+        }
+
+        let (controller, _queue) = control_channel(16);
+        let other = controller.clone();
+        assert_eq!(other.tuning(), Some(KeyedTuning::default()));
+        let just = KeyedTuning::new(crate::Temperament::Just, 2);
+        controller.set_tuning(just);
+        assert_eq!(other.tuning(), Some(just));
+        controller.set_tuning(Stretched);
+        assert_eq!(other.tuning(), None);
+    }
+}
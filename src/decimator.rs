@@ -0,0 +1,162 @@
+//! Half-band filtering for bringing oversampled audio back to the output
+//! rate
+
+use std::f32::consts::PI;
+
+/// Taps of each half-band stage; more gives a steeper cut at Nyquist
+const HALF_BAND_TAPS: usize = 47;
+
+/// Highest oversampling factor a [`Decimator`] can undo
+pub const MAX_OVERSAMPLING: usize = 4;
+
+/// Reduces audio rendered at 2x or 4x the output rate back to the output
+/// rate, through one half-band lowpass per halving so content above the
+/// output Nyquist is filtered out rather than folded back. Each stage
+/// delays its output by 23 of its input samples.
+pub struct Decimator {
+    stages: [HalfBand; 2],
+    factor: usize,
+}
+
+impl Decimator {
+    /// Create a decimator for `factor` times oversampling, rounded up to
+    /// 1, 2 or 4; at 1 it passes samples through
+    pub fn new(factor: usize) -> Self {
+        Self {
+            stages: [HalfBand::new(), HalfBand::new()],
+            factor: factor.clamp(1, MAX_OVERSAMPLING).next_power_of_two(),
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Filter and decimate `block` in place, returning the output samples
+    /// at its start. Its length should be a multiple of the factor.
+    pub fn process<'a>(&mut self, block: &'a mut [f32]) -> &'a [f32] {
+        let mut len = block.len();
+        for stage in &mut self.stages[..self.factor.trailing_zeros() as usize] {
+            len = stage.decimate(&mut block[..len]);
+        }
+        &block[..len]
+    }
+
+    /// Clear the filter history
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            *stage = HalfBand::new();
+        }
+    }
+}
+
+/// Windowed-sinc lowpass at half the input Nyquist, dropping every other
+/// sample
+#[derive(Clone, Copy)]
+struct HalfBand {
+    coefficients: [f32; HALF_BAND_TAPS],
+    history: [f32; 2 * HALF_BAND_TAPS],  // Stored twice, so the window is one slice
+    position: usize,
+}
+
+impl HalfBand {
+    fn new() -> Self {
+        let centre = (HALF_BAND_TAPS / 2) as f32;
+        let mut coefficients: [f32; HALF_BAND_TAPS] = std::array::from_fn(|n| {
+            let t = n as f32 - centre;
+            let sinc = if t == 0.0 { 0.5 } else { (0.5 * PI * t).sin() / (PI * t) };
+            // Blackman window
+            let x = 2.0 * PI * n as f32 / (HALF_BAND_TAPS - 1) as f32;
+            sinc * (0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos())
+        });
+        let sum: f32 = coefficients.iter().sum();
+        for c in &mut coefficients {
+            *c /= sum;
+        }
+        Self {
+            coefficients,
+            history: [0.0; 2 * HALF_BAND_TAPS],
+            position: 0,
+        }
+    }
+
+    /// Filter `block` and keep every other sample at its start, returning
+    /// how many were kept
+    fn decimate(&mut self, block: &mut [f32]) -> usize {
+        let len = block.len() / 2;
+        for i in 0..len {
+            self.push(block[2 * i]);
+            self.push(block[2 * i + 1]);
+            block[i] = self.output();
+        }
+        len
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.position = (self.position + HALF_BAND_TAPS - 1) % HALF_BAND_TAPS;
+        self.history[self.position] = sample;
+        self.history[self.position + HALF_BAND_TAPS] = sample;
+    }
+
+    fn output(&self) -> f32 {
+        let window = &self.history[self.position..self.position + HALF_BAND_TAPS];
+        window.iter().zip(&self.coefficients).map(|(x, c)| x * c).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FMParams, FMSynth};
+
+    /// RMS of a sine at `frequency` Hz run through `factor` times
+    /// decimation from an output rate of 48 kHz, once the filters settle
+    fn decimated_rms(factor: usize, frequency: f32) -> f32 {
+        let rate = 48000.0 * factor as f32;
+        let mut input: Vec<f32> = (0..9600 * factor)
+            .map(|n| (2.0 * PI * frequency * n as f32 / rate).sin())
+            .collect();
+        let mut decimator = Decimator::new(factor);
+        let output = decimator.process(&mut input);
+        assert_eq!(output.len(), 9600);
+        rms(&output[100..])
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn passband_is_unity_and_stopband_is_cut() {
+        for factor in [2, 4] {
+            let pass = decimated_rms(factor, 1000.0);
+            let gain = pass / std::f32::consts::FRAC_1_SQRT_2;
+            assert!((gain - 1.0).abs() < 0.01, "{}x passband gain {}", factor, gain);
+            // Above the output Nyquist, where it would otherwise alias
+            let stop = decimated_rms(factor, 36000.0);
+            assert!(stop < 0.01, "{}x stopband {}", factor, stop);
+        }
+    }
+
+    #[test]
+    fn oversampling_keeps_length_and_level() {
+        let render = |factor: usize| {
+            let mut synth = FMSynth::new(48000.0, FMParams::default()).unwrap();
+            synth.set_oversampling(factor);
+            synth.note_on(57, 1.0);
+            let (mut left, mut right) = (vec![0.0; 48000], vec![0.0; 48000]);
+            synth.process_stereo(&mut left[..24000], &mut right[..24000]);
+            synth.note_off(57);
+            synth.process_stereo(&mut left[24000..], &mut right[24000..]);
+            let end = left.iter().rposition(|sample| sample.abs() > 1e-4).unwrap();
+            (rms(&left[..24000]), end)
+        };
+        let (level, end) = render(1);
+        for factor in [2, 4] {
+            let (oversampled, oversampled_end) = render(factor);
+            let db = 20.0 * (oversampled / level).log10();
+            assert!(db.abs() < 0.5, "{}x is {} dB off", factor, db);
+            assert!(end.abs_diff(oversampled_end) < 480, "{}x ends at {}, not {}", factor, oversampled_end, end);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! Yamaha DX7 SysEx patch import
+//!
+//! DX7 voices have six operators and 32 algorithms, so importing onto the
+//! four-operator engine is lossy: the carriers and their loudest
+//! modulators are kept, and the closest of the eight [`ALGORITHMS`] is
+//! chosen. Envelopes map onto the engine's four-segment rate/level
+//! envelope with exponential segments, as the DX7's level curves sound,
+//! with an ADSR approximation alongside; keyboard scaling, detune,
+//! LFO and the pitch envelope are dropped.
+//!
+//! [`ALGORITHMS`]: crate::ALGORITHMS
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+use crate::{ALGORITHMS, EnvelopeCurve, FMParams, NUM_OPERATORS, OperatorParams, Parameter, Preset};
+
+/// Operators in a DX7 voice
+pub const DX7_OPERATORS: usize = 6;
+
+/// Modulation index of a modulator at full output level (99)
+const FULL_LEVEL_INDEX: f32 = 4.0 * PI;
+
+/// One operator of a DX7 voice, with values in the DX7's own ranges
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Dx7Operator {
+    /// Envelope rates R1-R4 (0-99)
+    pub rates: [u8; 4],
+    /// Envelope levels L1-L4 (0-99)
+    pub levels: [u8; 4],
+    /// Key velocity sensitivity (0-7)
+    pub velocity_sens: u8,
+    /// Output level (0-99)
+    pub output_level: u8,
+    /// Fixed frequency instead of a ratio
+    pub fixed: bool,
+    /// Coarse frequency (0-31)
+    pub coarse: u8,
+    /// Fine frequency (0-99)
+    pub fine: u8,
+}
+
+/// A DX7 voice as stored in a SysEx dump
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dx7Voice {
+    pub name: String,
+    /// Operators, op 1 first
+    pub operators: [Dx7Operator; DX7_OPERATORS],
+    /// Algorithm number (1-32)
+    pub algorithm: u8,
+    /// Feedback amount (0-7)
+    pub feedback: u8,
+}
+
+/// Routing of one DX7 algorithm, using the same bitmasks as
+/// [`Algorithm`](crate::Algorithm), plus the operator feedback runs to
+struct Dx7Algorithm {
+    modulators: [u8; DX7_OPERATORS],
+    carriers: u8,
+    feedback: usize,
+}
+
+const fn dx7(modulators: [u8; DX7_OPERATORS], carriers: u8, feedback: usize) -> Dx7Algorithm {
+    Dx7Algorithm { modulators, carriers, feedback }
+}
+
+/// The 32 DX7 algorithms. Feedback loops spanning several operators (4
+/// and 6) are treated as self-feedback on op 6.
+const DX7_ALGORITHMS: [Dx7Algorithm; 32] = [
+    dx7([0b000010, 0, 0b001000, 0b010000, 0b100000, 0], 0b000101, 5),
+    dx7([0b000010, 0, 0b001000, 0b010000, 0b100000, 0], 0b000101, 1),
+    dx7([0b000010, 0b000100, 0, 0b010000, 0b100000, 0], 0b001001, 5),
+    dx7([0b000010, 0b000100, 0, 0b010000, 0b100000, 0], 0b001001, 5),
+    dx7([0b000010, 0, 0b001000, 0, 0b100000, 0], 0b010101, 5),
+    dx7([0b000010, 0, 0b001000, 0, 0b100000, 0], 0b010101, 5),
+    dx7([0b000010, 0, 0b011000, 0, 0b100000, 0], 0b000101, 5),
+    dx7([0b000010, 0, 0b011000, 0, 0b100000, 0], 0b000101, 3),
+    dx7([0b000010, 0, 0b011000, 0, 0b100000, 0], 0b000101, 1),
+    dx7([0b000010, 0b000100, 0, 0b110000, 0, 0], 0b001001, 2),
+    dx7([0b000010, 0b000100, 0, 0b110000, 0, 0], 0b001001, 5),
+    dx7([0b000010, 0, 0b111000, 0, 0, 0], 0b000101, 1),
+    dx7([0b000010, 0, 0b111000, 0, 0, 0], 0b000101, 5),
+    dx7([0b000010, 0, 0b001000, 0b110000, 0, 0], 0b000101, 5),
+    dx7([0b000010, 0, 0b001000, 0b110000, 0, 0], 0b000101, 1),
+    dx7([0b010110, 0, 0b001000, 0, 0b100000, 0], 0b000001, 5),
+    dx7([0b010110, 0, 0b001000, 0, 0b100000, 0], 0b000001, 1),
+    dx7([0b001110, 0, 0, 0b010000, 0b100000, 0], 0b000001, 2),
+    dx7([0b000010, 0b000100, 0, 0b100000, 0b100000, 0], 0b011001, 5),
+    dx7([0b000100, 0b000100, 0, 0b110000, 0, 0], 0b001011, 2),
+    dx7([0b000100, 0b000100, 0, 0b100000, 0b100000, 0], 0b011011, 2),
+    dx7([0b000010, 0, 0b100000, 0b100000, 0b100000, 0], 0b011101, 5),
+    dx7([0, 0b000100, 0, 0b100000, 0b100000, 0], 0b011011, 5),
+    dx7([0, 0, 0b100000, 0b100000, 0b100000, 0], 0b011111, 5),
+    dx7([0, 0, 0, 0b100000, 0b100000, 0], 0b011111, 5),
+    dx7([0, 0b000100, 0, 0b110000, 0, 0], 0b001011, 5),
+    dx7([0, 0b000100, 0, 0b110000, 0, 0], 0b001011, 2),
+    dx7([0b000010, 0, 0b001000, 0b010000, 0, 0], 0b100101, 4),
+    dx7([0, 0, 0b001000, 0, 0b100000, 0], 0b010111, 5),
+    dx7([0, 0, 0b001000, 0b010000, 0, 0], 0b100111, 4),
+    dx7([0, 0, 0, 0, 0b100000, 0], 0b011111, 5),
+    dx7([0, 0, 0, 0, 0, 0], 0b111111, 5),
+];
+
+impl Dx7Voice {
+    /// Map the voice onto a four-operator patch, as described in the
+    /// module docs
+    pub fn to_preset(&self) -> Preset {
+        let routing = &DX7_ALGORITHMS[(self.algorithm.clamp(1, 32) - 1) as usize];
+        let kept = self.kept_operators(routing);
+        let (algorithm, slots) = closest_algorithm(routing, &kept);
+        
+        let mut params = FMParams {
+            algorithm,
+            modulation_index: FULL_LEVEL_INDEX,
+            operators: [OperatorParams { level: 0.0, ..OperatorParams::default() }; NUM_OPERATORS],
+            ..FMParams::default()
+        };
+        for (&dx_op, &slot) in kept.iter().zip(&slots) {
+            let op = &self.operators[dx_op];
+            let target = &mut params.operators[slot];
+            target.ratio = clamp("op1_ratio", ratio(op));
+            target.level = level_gain(op.output_level);
+            target.velocity_sens = op.velocity_sens.min(7) as f32 / 7.0;
+            if dx_op == routing.feedback {
+                target.feedback = self.feedback.min(7) as f32;
+            }
+            
+            // The louder of L1 and L2 is the peak; the R2 and R3 segments are
+            // folded into one decay to L3
+            let peak = level_gain(op.levels[0]).max(level_gain(op.levels[1])).max(1e-3);
+            target.attack = clamp("op1_attack", rate_seconds(op.rates[0]));
+            target.decay = clamp("op1_decay", rate_seconds(op.rates[1]) + rate_seconds(op.rates[2]));
+            target.sustain = (level_gain(op.levels[2]) / peak).min(1.0);
+            target.release = clamp("op1_release", rate_seconds(op.rates[3]));
+            target.decay_curve = EnvelopeCurve::Exponential;
+            target.release_curve = EnvelopeCurve::Exponential;
+            
+            // DX7 rates are speeds, so a segment's time depends on how far
+            // it moves. Each one starts where the previous ended, the first
+            // from L4.
+            target.rate_level = true;
+            for segment in 0..4 {
+                let from = op.levels[(segment + 3) % 4].min(99);
+                let distance = op.levels[segment].min(99).abs_diff(from) as f32 / 99.0;
+                target.segment_times[segment] =
+                    clamp("op1_time1", rate_seconds(op.rates[segment]) * distance);
+                target.segment_levels[segment] = level_gain(op.levels[segment]);
+            }
+        }
+        
+        Preset::new(self.name.trim_end(), params)
+    }
+
+    /// DX7 operators (numbered from 0) to keep: every carrier, then the
+    /// loudest modulators of kept operators, up to four
+    fn kept_operators(&self, routing: &Dx7Algorithm) -> Vec<usize> {
+        let loudest_first = |ops: &mut Vec<usize>| {
+            ops.sort_by_key(|&op| (std::cmp::Reverse(self.operators[op].output_level), op));
+        };
+        
+        let mut kept: Vec<usize> = (0..DX7_OPERATORS)
+            .filter(|&op| routing.carriers & (1 << op) != 0)
+            .collect();
+        loudest_first(&mut kept);
+        kept.truncate(NUM_OPERATORS);
+        
+        while kept.len() < NUM_OPERATORS {
+            let mut candidates: Vec<usize> = (0..DX7_OPERATORS)
+                .filter(|op| !kept.contains(op))
+                .filter(|&op| kept.iter().any(|&to| routing.modulators[to] & (1 << op) != 0))
+                .collect();
+            loudest_first(&mut candidates);
+            match candidates.first() {
+                Some(&op) => kept.push(op),
+                None => break,
+            }
+        }
+        kept
+    }
+}
+
+/// Find the engine algorithm and operator slots that best reproduce the
+/// kept part of a DX7 routing: fewest routing mismatches, then fewest
+/// unused carriers (which would dilute the carrier mix)
+fn closest_algorithm(routing: &Dx7Algorithm, kept: &[usize]) -> (usize, Vec<usize>) {
+    let mut best = (usize::MAX, usize::MAX, 1, Vec::new());
+    for (index, algorithm) in ALGORITHMS.iter().enumerate() {
+        for slots in assignments(kept.len()) {
+            let mut mismatches = 0;
+            for (i, &to) in kept.iter().enumerate() {
+                let carrier = routing.carriers & (1 << to) != 0;
+                if carrier != algorithm.is_carrier(slots[i]) {
+                    mismatches += 2;
+                }
+                for (j, &from) in kept.iter().enumerate() {
+                    let modulates = routing.modulators[to] & (1 << from) != 0;
+                    if i != j && modulates != algorithm.modulates(slots[j], slots[i]) {
+                        mismatches += 1;
+                    }
+                }
+            }
+            let unused_carriers = (0..NUM_OPERATORS)
+                .filter(|slot| !slots.contains(slot) && algorithm.is_carrier(*slot))
+                .count();
+            
+            if (mismatches, unused_carriers) < (best.0, best.1) {
+                best = (mismatches, unused_carriers, index + 1, slots);
+            }
+        }
+    }
+    (best.2, best.3)
+}
+
+/// Every way of placing `count` operators into distinct engine slots
+fn assignments(count: usize) -> Vec<Vec<usize>> {
+    if count == 0 {
+        return vec![Vec::new()];
+    }
+    let mut result = Vec::new();
+    for rest in assignments(count - 1) {
+        for slot in (0..NUM_OPERATORS).filter(|slot| !rest.contains(slot)) {
+            let mut slots = rest.clone();
+            slots.push(slot);
+            result.push(slots);
+        }
+    }
+    result
+}
+
+/// Frequency ratio of an operator. Fixed-frequency operators become the
+/// ratio that matches their frequency at A4.
+fn ratio(op: &Dx7Operator) -> f32 {
+    let fine = op.fine.min(99) as f32;
+    if op.fixed {
+        let frequency = 10.0_f32.powi((op.coarse % 4) as i32) * 10.0_f32.powf(fine / 100.0);
+        return frequency / 440.0;
+    }
+    let coarse = if op.coarse == 0 { 0.5 } else { op.coarse as f32 };
+    coarse * (1.0 + fine / 100.0)
+}
+
+/// Linear gain for a DX7 output or envelope level: 0.75 dB per step below
+/// 99, with 0 silent
+fn level_gain(level: u8) -> f32 {
+    match level.min(99) {
+        0 => 0.0,
+        level => 10.0_f32.powf(-0.75 * (99 - level) as f32 / 20.0),
+    }
+}
+
+/// Approximate seconds for a full-range envelope segment at a DX7 rate:
+/// about 40 s at 0, halving every six steps
+fn rate_seconds(rate: u8) -> f32 {
+    40.0 * 0.5_f32.powf(rate.min(99) as f32 / 6.0)
+}
+
+fn clamp(id: &str, value: f32) -> f32 {
+    Parameter::find(id).map_or(value, |param| param.clamp(value))
+}
+
+/// Decode a DX7 single-voice or 32-voice bank SysEx dump
+pub fn parse_sysex(bytes: &[u8]) -> Result<Vec<Dx7Voice>, SysexError> {
+    // F0, Yamaha ID, sub-status/channel, format, two size bytes
+    let (header, rest) = bytes.split_at_checked(6).ok_or(SysexError::Truncated)?;
+    if header[0] != 0xF0 || header[1] != 0x43 || header[2] & 0xF0 != 0 {
+        return Err(SysexError::NotDx7);
+    }
+    let (len, decode): (usize, fn(&[u8]) -> Dx7Voice) = match header[3] {
+        0 => (155, decode_single),
+        9 => (4096, decode_packed),
+        _ => return Err(SysexError::NotDx7),
+    };
+
+    let data = rest.get(..len).ok_or(SysexError::Truncated)?;
+    let checksum = *rest.get(len).ok_or(SysexError::Truncated)?;
+    let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    if sum.wrapping_add(checksum) & 0x7F != 0 {
+        return Err(SysexError::Checksum);
+    }
+
+    Ok(data.chunks(if len == 155 { 155 } else { 128 }).map(decode).collect())
+}
+
+/// Read and decode a `.syx` file
+pub fn load_syx(path: impl AsRef<Path>) -> Result<Vec<Dx7Voice>, SysexError> {
+    parse_sysex(&std::fs::read(path)?)
+}
+
+/// Unpacked single-voice (VCED) layout: 21 bytes per operator, op 6 first
+fn decode_single(data: &[u8]) -> Dx7Voice {
+    let operators = std::array::from_fn(|i| {
+        let op = &data[(DX7_OPERATORS - 1 - i) * 21..];
+        Dx7Operator {
+            rates: [op[0], op[1], op[2], op[3]],
+            levels: [op[4], op[5], op[6], op[7]],
+            velocity_sens: op[15],
+            output_level: op[16],
+            fixed: op[17] & 1 != 0,
+            coarse: op[18],
+            fine: op[19],
+        }
+    });
+    Dx7Voice {
+        name: decode_name(&data[145..155]),
+        operators,
+        algorithm: (data[134] & 0x1F) + 1,
+        feedback: data[135] & 0x07,
+    }
+}
+
+/// Packed bank (VMEM) layout: 128 bytes per voice, 17 per operator
+fn decode_packed(data: &[u8]) -> Dx7Voice {
+    let operators = std::array::from_fn(|i| {
+        let op = &data[(DX7_OPERATORS - 1 - i) * 17..];
+        Dx7Operator {
+            rates: [op[0], op[1], op[2], op[3]],
+            levels: [op[4], op[5], op[6], op[7]],
+            velocity_sens: (op[13] >> 2) & 0x07,
+            output_level: op[14],
+            fixed: op[15] & 1 != 0,
+            coarse: (op[15] >> 1) & 0x1F,
+            fine: op[16],
+        }
+    });
+    Dx7Voice {
+        name: decode_name(&data[118..128]),
+        operators,
+        algorithm: (data[110] & 0x1F) + 1,
+        feedback: data[111] & 0x07,
+    }
+}
+
+fn decode_name(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { ' ' })
+        .collect()
+}
+
+/// Error returned when a SysEx dump can't be imported
+#[derive(Debug)]
+pub enum SysexError {
+    Io(std::io::Error),
+    /// Not a DX7 voice or bank dump
+    NotDx7,
+    /// Shorter than its format requires
+    Truncated,
+    /// Data doesn't match its checksum
+    Checksum,
+}
+
+impl std::fmt::Display for SysexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SysexError::Io(err) => write!(f, "{}", err),
+            SysexError::NotDx7 => write!(f, "Not a DX7 voice or bank dump"),
+            SysexError::Truncated => write!(f, "SysEx dump is truncated"),
+            SysexError::Checksum => write!(f, "SysEx checksum mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for SysexError {}
+
+impl From<std::io::Error> for SysexError {
+    fn from(err: std::io::Error) -> Self {
+        SysexError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wrap `data` in a SysEx dump of `format` with a valid checksum
+    fn dump(format: u8, data: &[u8]) -> Vec<u8> {
+        let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let len = data.len() as u16;
+        let mut bytes = vec![0xF0, 0x43, 0x00, format, (len >> 7) as u8, (len & 0x7F) as u8];
+        bytes.extend_from_slice(data);
+        bytes.extend([sum.wrapping_neg() & 0x7F, 0xF7]);
+        bytes
+    }
+
+    /// An unpacked voice on algorithm 1 with operators 1-4 at full level,
+    /// coarse frequencies 1, 2, 1 (fine 50) and 0
+    fn single_voice() -> Vec<u8> {
+        let mut data = vec![0; 155];
+        for (op, (coarse, fine)) in [(1, 0), (2, 0), (1, 50), (0, 0)].into_iter().enumerate() {
+            let at = (DX7_OPERATORS - 1 - op) * 21;
+            data[at..at + 8].copy_from_slice(&[99, 99, 99, 99, 99, 99, 99, 0]);
+            data[at + 16] = 99;
+            data[at + 18] = coarse;
+            data[at + 19] = fine;
+        }
+        data[134] = 0;  // Algorithm 1
+        data[135] = 7;
+        data[145..155].copy_from_slice(b"TEST VOICE");
+        data
+    }
+
+    #[test]
+    fn single_voice_dump() {
+        let voices = parse_sysex(&dump(0, &single_voice())).unwrap();
+        assert_eq!(voices.len(), 1);
+        let voice = &voices[0];
+        assert_eq!(voice.name, "TEST VOICE");
+        assert_eq!((voice.algorithm, voice.feedback), (1, 7));
+        assert_eq!(voice.operators[0].output_level, 99);
+        assert_eq!((voice.operators[2].coarse, voice.operators[2].fine), (1, 50));
+        assert_eq!(voice.operators[5], Dx7Operator::default());
+    }
+
+    #[test]
+    fn bank_dump() {
+        let mut data = vec![0; 4096];
+        for (index, voice) in data.chunks_mut(128).enumerate() {
+            voice[110] = index as u8;
+            voice[118..128].copy_from_slice(format!("VOICE {:<4}", index + 1).as_bytes());
+        }
+        // Op 1 of the last voice: level 90, coarse 3, fixed
+        data[31 * 128 + 5 * 17 + 14] = 90;
+        data[31 * 128 + 5 * 17 + 15] = 3 << 1 | 1;
+
+        let voices = parse_sysex(&dump(9, &data)).unwrap();
+        assert_eq!(voices.len(), 32);
+        assert_eq!(voices[0].name, "VOICE 1   ");
+        assert_eq!((voices[31].name.as_str(), voices[31].algorithm), ("VOICE 32  ", 32));
+        let op = voices[31].operators[0];
+        assert_eq!((op.output_level, op.coarse, op.fixed), (90, 3, true));
+    }
+
+    #[test]
+    fn truncated_dump() {
+        let bytes = dump(0, &single_voice());
+        assert!(matches!(parse_sysex(&bytes[..100]), Err(SysexError::Truncated)));
+        // Missing only the checksum
+        assert!(matches!(parse_sysex(&bytes[..6 + 155]), Err(SysexError::Truncated)));
+        assert!(matches!(parse_sysex(&bytes[..4]), Err(SysexError::Truncated)));
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut bytes = dump(0, &single_voice());
+        bytes[6 + 155] ^= 1;
+        assert!(matches!(parse_sysex(&bytes), Err(SysexError::Checksum)));
+    }
+
+    #[test]
+    fn other_dumps_are_rejected() {
+        let mut bytes = dump(0, &single_voice());
+        bytes[1] = 0x41;
+        assert!(matches!(parse_sysex(&bytes), Err(SysexError::NotDx7)));
+    }
+
+    #[test]
+    fn voice_maps_onto_the_closest_algorithm() {
+        // DX7 algorithm 1 keeps its carriers 1 and 3 and their modulators
+        // 2 and 4, which is the engine's 2>1 + 4>3
+        let voice = &parse_sysex(&dump(0, &single_voice())).unwrap()[0];
+        let preset = voice.to_preset();
+        let params = &preset.params;
+        assert_eq!(ALGORITHMS[params.algorithm - 1].name, "2>1 + 4>3");
+        let ratios: Vec<f32> = params.operators.iter().map(|op| op.ratio).collect();
+        assert_eq!(ratios, [1.0, 2.0, 1.5, 0.5]);
+        assert!(params.operators.iter().all(|op| op.level == 1.0));
+        // Feedback was on op 6, which is dropped
+        assert!(params.operators.iter().all(|op| op.feedback == 0.0));
+    }
+}
@@ -0,0 +1,243 @@
+//! Full-screen patch editor for the terminal, playable while editing
+//!
+//! The screen is drawn with plain ANSI escapes and keys are read raw
+//! through `stty`, rather than with ratatui and crossterm, so the editor
+//! adds no dependencies and needs a Unix terminal; elsewhere the preset
+//! demo plays instead. Only [`draw`] and the
+//! keyboard module's `read_keys`, `RawTerminal` and `FullScreen` touch the
+//! terminal, so moving to ratatui would replace just those.
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use fm_synth::{
+    ALGORITHMS, Controller, FMParams, KeyedTuning, NUM_OPERATORS, Parameter, Temperament, Unit,
+};
+
+use crate::PITCH_CLASSES;
+use crate::keyboard::{FullScreen, Key, Keyboard, RawTerminal, read_keys};
+
+/// Patch-wide parameters, one per row above the operator table
+const PATCH_ROWS: [&str; 8] = [
+    "algorithm",
+    "modulation_index",
+    "amplitude",
+    "index_env_amount",
+    "index_attack",
+    "index_decay",
+    "index_sustain",
+    "index_release",
+];
+
+/// Rows for the tuning, which isn't part of the patch, between the
+/// patch-wide parameters and the operator table
+const TUNING_ROWS: [&str; 2] = ["Temperament", "Tuning Root"];
+
+/// Operator parameters, one per row of the table, without their `opN_`
+const OPERATOR_ROWS: [&str; 8] = [
+    "ratio", "level", "feedback", "input", "attack", "decay", "sustain", "release",
+];
+
+/// Characters per column of the operator table
+const COLUMN_WIDTH: usize = 10;
+
+/// Where the editor's cursor is: a row of [`PATCH_ROWS`], [`TUNING_ROWS`]
+/// and then [`OPERATOR_ROWS`], and the operator for rows of the table
+struct Cursor {
+    row: usize,
+    operator: usize,
+}
+
+impl Cursor {
+    /// Id of the parameter under the cursor, if it is on one
+    fn id(&self) -> Option<String> {
+        match self.row.checked_sub(PATCH_ROWS.len() + TUNING_ROWS.len()) {
+            Some(row) => Some(format!("op{}_{}", self.operator + 1, OPERATOR_ROWS[row])),
+            None => PATCH_ROWS.get(self.row).map(|id| id.to_string()),
+        }
+    }
+
+    /// Index into [`TUNING_ROWS`] of the row under the cursor, if it is one
+    fn tuning_row(&self) -> Option<usize> {
+        self.row.checked_sub(PATCH_ROWS.len()).filter(|&row| row < TUNING_ROWS.len())
+    }
+}
+
+/// Edit the patch in the raw `terminal` until Enter is pressed, playing it
+/// from the piano rows meanwhile. [ and ] step through `presets`, which
+/// must not be empty.
+pub fn run_editor(
+    synth: &Controller,
+    _terminal: RawTerminal,
+    sample_rate: f32,
+    presets: Vec<(&'static str, FMParams)>,
+) -> anyhow::Result<()> {
+    let mut preset = 0;
+    let mut params = presets[preset].1.clone();
+    synth.set_params(params.clone())?;
+
+    let _screen = FullScreen::enter();
+    let keys = read_keys();
+    let mut keyboard = Keyboard::new(sample_rate);
+    // Other tunings show as equal temperament until one is picked here
+    let mut tuning = synth.tuning().unwrap_or_default();
+    let mut cursor = Cursor { row: 0, operator: 0 };
+    let rows = PATCH_ROWS.len() + TUNING_ROWS.len() + OPERATOR_ROWS.len();
+    // Why the last edit or preset change was refused
+    let mut status = String::new();
+    draw(&params, &tuning, presets[preset].0, &cursor, keyboard.octave(), &status)?;
+    loop {
+        let key = keys.recv_timeout(Duration::from_millis(10));
+        keyboard.release_due(synth);
+        let key = match key {
+            Ok(key) => key,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        status.clear();
+        match key {
+            Key::Up => cursor.row = (cursor.row + rows - 1) % rows,
+            Key::Down => cursor.row = (cursor.row + 1) % rows,
+            Key::Left | Key::Right if cursor.tuning_row().is_some() => {
+                let up = key == Key::Right;
+                if cursor.tuning_row() == Some(0) {
+                    let all = Temperament::ALL;
+                    let index = all.iter().position(|&t| t == tuning.temperament).unwrap_or(0);
+                    let index = if up { index + 1 } else { index + all.len() - 1 };
+                    tuning.temperament = all[index % all.len()];
+                } else {
+                    tuning.root = (tuning.root + if up { 1 } else { 11 }) % 12;
+                }
+                synth.set_tuning(tuning);
+            }
+            Key::Left | Key::Right => {
+                let id = cursor.id().unwrap_or_default();
+                if let (Some(param), Some(value)) = (Parameter::find(&id), params.get(&id)) {
+                    let mut edited = params.clone();
+                    let result = edited.set(param.id, step(param, value, key == Key::Right))
+                        .and_then(|()| synth.set_params(edited.clone()));
+                    match result {
+                        Ok(()) => params = edited,
+                        Err(err) => status = err.to_string(),
+                    }
+                }
+            }
+            Key::Char(b'\t') => cursor.operator = (cursor.operator + 1) % NUM_OPERATORS,
+            Key::BackTab => cursor.operator = (cursor.operator + NUM_OPERATORS - 1) % NUM_OPERATORS,
+            Key::Char(key) if keyboard.press(synth, key) => continue,
+            Key::Char(key @ (b'-' | b'=')) => keyboard.shift_octave(key == b'='),
+            Key::Char(key @ (b'[' | b']')) => {
+                let next = if key == b'[' {
+                    (preset + presets.len() - 1) % presets.len()
+                } else {
+                    (preset + 1) % presets.len()
+                };
+                match synth.switch_params(presets[next].1.clone()) {
+                    Ok(()) => (preset, params) = (next, presets[next].1.clone()),
+                    Err(err) => status = format!("{}: {}", presets[next].0, err),
+                }
+            }
+            Key::Char(b' ') => keyboard.release_all(synth),
+            Key::Char(b'\n' | b'\r') => break,
+            Key::Char(_) | Key::Escape => continue,
+        }
+        draw(&params, &tuning, presets[preset].0, &cursor, keyboard.octave(), &status)?;
+    }
+    keyboard.release_all(synth);
+    Ok(())
+}
+
+/// The value one step up or down from `value`: a semitone for
+/// frequencies, about 1 dB for levels, a tenth more or less for times,
+/// the next whole number for counts and choices, and a hundredth of the
+/// range otherwise
+fn step(param: &Parameter, value: f32, up: bool) -> f32 {
+    let sign = if up { 1.0 } else { -1.0 };
+    let next = match param.unit {
+        Unit::Hz => value * 2.0_f32.powf(sign / 12.0),
+        Unit::Decibels => (value * 10.0_f32.powf(sign / 20.0)).max(if up { 0.001 } else { 0.0 }),
+        Unit::Millis => (value * 1.1_f32.powf(sign)).max(if up { 0.001 } else { 0.0 }),
+        Unit::Integer | Unit::Choice(_) => value.round() + sign,
+        Unit::Toggle => if up { 1.0 } else { 0.0 },
+        _ => value + sign * (param.max - param.min) / 100.0,
+    };
+    param.clamp(next)
+}
+
+/// Redraw the whole screen, with `status` on the last line
+fn draw(
+    params: &FMParams,
+    tuning: &KeyedTuning,
+    preset: &str,
+    cursor: &Cursor,
+    octave: u8,
+    status: &str,
+) -> anyhow::Result<()> {
+    let algorithm = &ALGORITHMS[params.algorithm.clamp(1, ALGORITHMS.len()) - 1];
+    let highlight = |text: &str, selected: bool| {
+        if selected { format!("\x1b[7m{}\x1b[0m", text) } else { text.to_string() }
+    };
+
+    let mut screen = String::from("\x1b[H\x1b[J");
+    writeln!(screen, " FM patch editor    Preset: {}    Octave {}", preset, octave)?;
+    writeln!(screen)?;
+    for (row, id) in PATCH_ROWS.iter().enumerate() {
+        let (Some(param), Some(value)) = (Parameter::find(id), params.get(id)) else {
+            continue;
+        };
+        let mut text = param.format(value);
+        if *id == "algorithm" {
+            text = format!("{}  {}", text, algorithm.name);
+        }
+        writeln!(screen, "   {:<16}{}", param.name, highlight(&text, cursor.row == row))?;
+    }
+    for (row, name) in TUNING_ROWS.iter().enumerate() {
+        let text = match row {
+            0 => tuning.temperament.name(),
+            _ => PITCH_CLASSES[tuning.root as usize],
+        };
+        let selected = cursor.tuning_row() == Some(row);
+        writeln!(screen, "   {:<16}{}", name, highlight(text, selected))?;
+    }
+    writeln!(screen)?;
+
+    // Carriers are starred in the header
+    write!(screen, "   {:<16}", "")?;
+    for operator in 0..NUM_OPERATORS {
+        let carrier = if algorithm.carriers & 1 << operator != 0 { "*" } else { "" };
+        write!(screen, "{:<COLUMN_WIDTH$}", format!("Op{}{}", operator + 1, carrier))?;
+    }
+    writeln!(screen)?;
+    for (row, suffix) in OPERATOR_ROWS.iter().enumerate() {
+        let row = row + PATCH_ROWS.len() + TUNING_ROWS.len();
+        let Some(param) = Parameter::find(&format!("op1_{}", suffix)) else {
+            continue;
+        };
+        // Names are "Op1 Ratio" and so on; the header gives the operator
+        let name = param.name.split_once(' ').map_or(param.name, |(_, name)| name);
+        write!(screen, "   {:<16}", name)?;
+        for operator in 0..NUM_OPERATORS {
+            let id = format!("op{}_{}", operator + 1, suffix);
+            let text = params.get(&id).map_or_else(String::new, |value| param.format(value));
+            let selected = cursor.row == row && cursor.operator == operator;
+            // Pad before highlighting, so escape codes don't count
+            let padding = COLUMN_WIDTH.saturating_sub(text.len());
+            write!(screen, "{}{}", highlight(&text, selected), " ".repeat(padding))?;
+        }
+        writeln!(screen)?;
+    }
+    writeln!(screen)?;
+    writeln!(screen, " Up/Down choose a parameter and Left/Right change it; Tab moves between")?;
+    writeln!(screen, " operators (* marks carriers) and [ ] step through the presets.")?;
+    writeln!(screen, " Z-comma and Q-P play, - = shift the octave, space releases every note")?;
+    writeln!(screen, " and Enter quits.")?;
+    writeln!(screen)?;
+    writeln!(screen, " {}", status)?;
+
+    let mut stdout = std::io::stdout();
+    stdout.write_all(screen.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,399 @@
+//! ADSR or DX7-style rate/level envelope with linear or curved stages,
+//! optionally looping while held
+
+use serde::{Deserialize, Serialize};
+
+/// How steeply curved stages bend; higher is more extreme
+const CURVE_STEEPNESS: f32 = 5.0;
+
+/// Shape of one envelope stage between its start and end levels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum EnvelopeCurve {
+    /// Constant rate throughout
+    #[default]
+    Linear,
+    /// Fast at first, slowing as it nears the end level, like a capacitor
+    /// charging: natural-sounding decays and releases, snappy attacks
+    Exponential,
+    /// Slow at first, speeding up towards the end level: swelling attacks
+    /// and decays that hold before dropping away
+    Logarithmic,
+}
+
+impl EnvelopeCurve {
+    pub const ALL: [EnvelopeCurve; 3] = [
+        EnvelopeCurve::Linear,
+        EnvelopeCurve::Exponential,
+        EnvelopeCurve::Logarithmic,
+    ];
+
+    /// Names in [`ALL`](Self::ALL) order, as shown by the parameter table
+    pub const NAMES: &'static [&'static str] = &["linear", "exp", "log"];
+
+    /// Look up by position in [`ALL`](Self::ALL)
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Fraction of the way from start to end level at `progress` (0.0 -
+    /// 1.0) through the stage
+    fn shape(self, progress: f32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            EnvelopeCurve::Linear => progress,
+            EnvelopeCurve::Exponential => {
+                (1.0 - (-CURVE_STEEPNESS * progress).exp()) / (1.0 - (-CURVE_STEEPNESS).exp())
+            }
+            EnvelopeCurve::Logarithmic => {
+                ((CURVE_STEEPNESS * progress).exp() - 1.0) / (CURVE_STEEPNESS.exp() - 1.0)
+            }
+        }
+    }
+}
+
+/// Which segments of a rate/level envelope repeat while the key is held
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum EnvelopeLoop {
+    /// Hold the third level until release
+    #[default]
+    Off,
+    /// Swing back and forth between the second and third levels
+    FromSecond,
+    /// Go back to the first segment each time the third level is reached
+    FromFirst,
+}
+
+impl EnvelopeLoop {
+    pub const ALL: [EnvelopeLoop; 3] = [
+        EnvelopeLoop::Off,
+        EnvelopeLoop::FromSecond,
+        EnvelopeLoop::FromFirst,
+    ];
+
+    /// Names in [`ALL`](Self::ALL) order, as shown by the parameter table
+    pub const NAMES: &'static [&'static str] = &["off", "2-3", "1-3"];
+
+    /// Look up by position in [`ALL`](Self::ALL)
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Segment the loop jumps back to after the third, if it loops
+    fn restart(self) -> Option<usize> {
+        match self {
+            EnvelopeLoop::Off => None,
+            EnvelopeLoop::FromSecond => Some(1),
+            EnvelopeLoop::FromFirst => Some(0),
+        }
+    }
+}
+
+/// ADSR Envelope generator
+#[derive(Clone)]
+pub struct Envelope {
+    attack: f32,   // Attack time in seconds
+    decay: f32,    // Decay time in seconds
+    sustain: f32,  // Sustain level (0.0 - 1.0)
+    release: f32,  // Release time in seconds
+    attack_curve: EnvelopeCurve,
+    decay_curve: EnvelopeCurve,
+    release_curve: EnvelopeCurve,
+    segments: Option<Segments>,  // Used instead of the ADSR stages when set
+    envelope_loop: EnvelopeLoop,
+    
+    sample_rate: f32,
+    state: EnvelopeState,
+    level: f32,
+    from: f32,  // Level the release or current segment started from
+    time: f32,
+}
+
+/// Times in seconds and levels (0.0 - 1.0) of a four-segment envelope
+#[derive(Clone, Copy, Default)]
+struct Segments {
+    times: [f32; 4],
+    levels: [f32; 4],
+}
+
+#[derive(Clone, PartialEq)]
+enum EnvelopeState {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    /// Moving through a rate/level segment (0 - 3, 3 being the release)
+    Segment(usize),
+}
+
+impl Envelope {
+    /// Create an idle envelope running at `sample_rate` Hz
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            attack: 0.01,
+            decay: 0.1,
+            sustain: 0.7,
+            release: 0.5,
+            attack_curve: EnvelopeCurve::Linear,
+            decay_curve: EnvelopeCurve::Linear,
+            release_curve: EnvelopeCurve::Linear,
+            segments: None,
+            envelope_loop: EnvelopeLoop::Off,
+            sample_rate,
+            state: EnvelopeState::Idle,
+            level: 0.0,
+            from: 0.0,
+            time: 0.0,
+        }
+    }
+
+    /// Set stage times in seconds and the sustain level (0.0 - 1.0), and
+    /// use the ADSR stages
+    pub fn set_adsr(&mut self, attack: f32, decay: f32, sustain: f32, release: f32) {
+        self.attack = attack;
+        self.decay = decay;
+        self.sustain = sustain;
+        self.release = release;
+        self.segments = None;
+    }
+
+    /// Use a four-segment rate/level envelope, DX7 style, instead of the
+    /// ADSR stages. Starting from `levels[3]`, a note moves through
+    /// `levels[0]`, `levels[1]` and `levels[2]`, taking `times[i]` seconds
+    /// over segment `i`, and holds the third level while the key is down.
+    /// On release it moves to `levels[3]` over `times[3]`, then stops.
+    pub fn set_segments(&mut self, times: [f32; 4], levels: [f32; 4]) {
+        self.segments = Some(Segments { times, levels });
+    }
+
+    /// Repeat rate/level segments while the key is held, for pulsing
+    /// without a sequencer. ADSR envelopes don't loop.
+    pub fn set_loop(&mut self, envelope_loop: EnvelopeLoop) {
+        self.envelope_loop = envelope_loop;
+    }
+
+    /// Set the shape of the attack, decay and release stages; the first,
+    /// middle two and last segments of a rate/level envelope
+    pub fn set_curves(&mut self, attack: EnvelopeCurve, decay: EnvelopeCurve, release: EnvelopeCurve) {
+        self.attack_curve = attack;
+        self.decay_curve = decay;
+        self.release_curve = release;
+    }
+
+    /// Start the attack stage, or the first segment
+    pub fn trigger(&mut self) {
+        match self.segments {
+            Some(segments) => {
+                self.state = EnvelopeState::Segment(0);
+                self.from = segments.levels[3];
+            }
+            None => self.state = EnvelopeState::Attack,
+        }
+        self.time = 0.0;
+    }
+
+    /// Enter the release stage unless already idle, falling from the
+    /// current level
+    pub fn release(&mut self) {
+        if self.state != EnvelopeState::Idle {
+            self.state = match self.segments {
+                Some(_) => EnvelopeState::Segment(3),
+                None => EnvelopeState::Release,
+            };
+            self.from = self.level;
+            self.time = 0.0;
+        }
+    }
+
+    /// Stop immediately, without a release
+    pub fn reset(&mut self) {
+        self.state = EnvelopeState::Idle;
+        self.level = 0.0;
+    }
+
+    /// True until the release stage has finished
+    pub fn is_active(&self) -> bool {
+        self.state != EnvelopeState::Idle
+    }
+
+    /// Level output by the latest [`process`](Self::process)
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Fill `output` with the levels of as many calls to
+    /// [`process`](Self::process), filling at once while idle or holding
+    pub fn process_block(&mut self, output: &mut [f32]) {
+        let held = match self.state {
+            EnvelopeState::Idle => Some(0.0),
+            EnvelopeState::Sustain => {
+                Some(self.segments.map_or(self.sustain, |segments| segments.levels[2]))
+            }
+            _ => None,
+        };
+        match held {
+            // Holding stages don't read the stage time, so it isn't advanced
+            Some(level) => {
+                self.level = level;
+                output.fill(level);
+            }
+            None => {
+                for sample in output {
+                    *sample = self.process();
+                }
+            }
+        }
+    }
+
+    /// Advance by one sample and return the current level
+    pub fn process(&mut self) -> f32 {
+        let dt = 1.0 / self.sample_rate;
+        
+        match self.state {
+            EnvelopeState::Idle => {
+                self.level = 0.0;
+            }
+            EnvelopeState::Attack => {
+                self.level = self.attack_curve.shape(self.time / self.attack);
+                if self.time >= self.attack {
+                    self.state = EnvelopeState::Decay;
+                    self.time = 0.0;
+                }
+            }
+            EnvelopeState::Decay => {
+                self.level = 1.0 - (1.0 - self.sustain) * self.decay_curve.shape(self.time / self.decay);
+                if self.time >= self.decay {
+                    self.state = EnvelopeState::Sustain;
+                    self.time = 0.0;
+                }
+            }
+            EnvelopeState::Sustain => {
+                self.level = self.segments.map_or(self.sustain, |segments| segments.levels[2]);
+            }
+            EnvelopeState::Release => {
+                self.level = self.from
+                    * (1.0 - self.release_curve.shape(self.time / self.release));
+                if self.time >= self.release {
+                    self.state = EnvelopeState::Idle;
+                    self.level = 0.0;
+                }
+            }
+            EnvelopeState::Segment(index) => {
+                // Switching to ADSR mid-segment runs out the note at once
+                let Segments { times, levels } = self.segments.unwrap_or_default();
+                let curve = match index {
+                    0 => self.attack_curve,
+                    3 => self.release_curve,
+                    _ => self.decay_curve,
+                };
+                let progress = if times[index] > 0.0 { self.time / times[index] } else { 1.0 };
+                self.level = self.from + (levels[index] - self.from) * curve.shape(progress);
+                if progress >= 1.0 {
+                    self.from = levels[index];
+                    self.time = 0.0;
+                    self.state = match index {
+                        0 | 1 => EnvelopeState::Segment(index + 1),
+                        2 => match self.envelope_loop.restart() {
+                            Some(restart) => EnvelopeState::Segment(restart),
+                            None => EnvelopeState::Sustain,
+                        },
+                        _ => {
+                            self.level = 0.0;
+                            EnvelopeState::Idle
+                        }
+                    };
+                }
+            }
+        }
+        
+        self.time += dt;
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rate/level envelope at 1 kHz, so each 0.1 s segment is 100 samples:
+    /// up to 1.0, down to 0.3, up to 0.6, then released to 0
+    fn segments(envelope_loop: EnvelopeLoop) -> Envelope {
+        let mut envelope = Envelope::new(1000.0);
+        envelope.set_segments([0.1; 4], [1.0, 0.3, 0.6, 0.0]);
+        envelope.set_loop(envelope_loop);
+        envelope.trigger();
+        envelope
+    }
+
+    fn run(envelope: &mut Envelope, samples: usize) -> Vec<f32> {
+        (0..samples).map(|_| envelope.process()).collect()
+    }
+
+    /// First sample from `from` on at `level`
+    fn reaches(levels: &[f32], from: usize, level: f32) -> Option<usize> {
+        (from..levels.len()).find(|&n| (levels[n] - level).abs() < 1e-4)
+    }
+
+    #[test]
+    fn segments_reach_each_level_in_order() {
+        let levels = run(&mut segments(EnvelopeLoop::Off), 500);
+        let first = reaches(&levels, 0, 1.0).unwrap();
+        let second = reaches(&levels, first, 0.3).unwrap();
+        let third = reaches(&levels, second, 0.6).unwrap();
+        for (at, expected) in [(first, 100), (second, 200), (third, 300)] {
+            assert!(at.abs_diff(expected) <= 2, "reached at {} rather than {}", at, expected);
+        }
+        // The third level holds while the key is down
+        assert!(levels[third..].iter().all(|&level| level == 0.6));
+    }
+
+    #[test]
+    fn release_from_a_middle_segment() {
+        let mut envelope = segments(EnvelopeLoop::Off);
+        let before = *run(&mut envelope, 150).last().unwrap();
+        assert!(before < 1.0 && before > 0.3);
+        envelope.release();
+        let levels = run(&mut envelope, 120);
+        // Falls from where it was, without jumping
+        assert!((levels[0] - before).abs() < 0.02);
+        assert!(levels.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(*levels.last().unwrap(), 0.0);
+        assert!(!envelope.is_active());
+    }
+
+    #[test]
+    fn loops_repeat_while_held_and_stop_on_release() {
+        // 2-3 swings between the second and third levels
+        let mut envelope = segments(EnvelopeLoop::FromSecond);
+        let levels = run(&mut envelope, 1000);
+        let held = &levels[310..];
+        assert!(held.iter().all(|&level| (0.3 - 1e-4..=0.6 + 1e-4).contains(&level)));
+        let dips = held.windows(2)
+            .filter(|pair| pair[1] <= 0.3 + 1e-4 && pair[0] > 0.3 + 1e-4)
+            .count();
+        assert!(dips >= 3, "only {} dips to the second level", dips);
+
+        // 1-3 goes back to the first level each time
+        let mut looped = segments(EnvelopeLoop::FromFirst);
+        let levels = run(&mut looped, 1000);
+        let peaks = levels[110..].windows(2)
+            .filter(|pair| pair[1] >= 1.0 - 1e-4 && pair[0] < 1.0 - 1e-4)
+            .count();
+        assert!(peaks >= 2, "only {} returns to the first level", peaks);
+
+        for envelope in [&mut envelope, &mut looped] {
+            envelope.release();
+            let levels = run(envelope, 120);
+            assert_eq!(*levels.last().unwrap(), 0.0);
+            assert!(!envelope.is_active());
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! JSON-lines log of performance events, for analysis and offline replay
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Command, FMParams, MidiMessage, bend_amount};
+
+/// A logged event. Each line of a log is one of these, tagged by `event`
+/// and stamped with the output sample it was sent at.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LoggedEvent {
+    /// First line of every log
+    Session { sample_rate: f32 },
+    NoteOn { note: u8, velocity: f32 },
+    NoteOff { note: u8 },
+    AllNotesOff,
+    PitchBend { amount: f32 },
+    ModWheel { amount: f32 },
+    ControlChange { controller: u8, value: u8 },
+    Params { params: Box<FMParams> },
+    SwitchParams { params: Box<FMParams> },
+}
+
+impl LoggedEvent {
+    /// The loggable part of a command; tuning changes aren't logged
+    pub fn from_command(command: &Command) -> Option<Self> {
+        let event = match command {
+            Command::NoteOn { note, velocity } => LoggedEvent::NoteOn { note: *note, velocity: *velocity },
+            Command::NoteOff { note } => LoggedEvent::NoteOff { note: *note },
+            Command::AllNotesOff => LoggedEvent::AllNotesOff,
+            Command::PitchBend(amount) => LoggedEvent::PitchBend { amount: *amount },
+            Command::ModWheel(amount) => LoggedEvent::ModWheel { amount: *amount },
+            Command::Midi(message) => match *message {
+                MidiMessage::NoteOn { note, velocity, .. } => {
+                    LoggedEvent::NoteOn { note, velocity: velocity as f32 / 127.0 }
+                }
+                MidiMessage::NoteOff { note, .. } => LoggedEvent::NoteOff { note },
+                MidiMessage::PitchBend { value, .. } => {
+                    LoggedEvent::PitchBend { amount: bend_amount(value) }
+                }
+                MidiMessage::ControlChange { controller, value, .. } => {
+                    LoggedEvent::ControlChange { controller, value }
+                }
+            },
+            Command::SetParams(params) => LoggedEvent::Params { params: Box::new(params.as_ref().clone()) },
+            Command::SwitchParams(params) => {
+                LoggedEvent::SwitchParams { params: Box::new(params.as_ref().clone()) }
+            }
+            Command::SetTuning(_) => return None,
+        };
+        Some(event)
+    }
+
+    /// The command that replays this event, if it changes the synth
+    pub fn to_command(&self) -> Option<Command> {
+        let command = match self {
+            LoggedEvent::Session { .. } => return None,
+            LoggedEvent::NoteOn { note, velocity } => Command::NoteOn { note: *note, velocity: *velocity },
+            LoggedEvent::NoteOff { note } => Command::NoteOff { note: *note },
+            LoggedEvent::AllNotesOff => Command::AllNotesOff,
+            LoggedEvent::PitchBend { amount } => Command::PitchBend(*amount),
+            LoggedEvent::ModWheel { amount } => Command::ModWheel(*amount),
+            LoggedEvent::ControlChange { controller, value } => {
+                Command::Midi(MidiMessage::ControlChange { channel: 0, controller: *controller, value: *value })
+            }
+            LoggedEvent::Params { params } => Command::SetParams(Arc::new(params.as_ref().clone())),
+            LoggedEvent::SwitchParams { params } => Command::SwitchParams(Arc::new(params.as_ref().clone())),
+        };
+        Some(command)
+    }
+}
+
+/// One line of a log
+#[derive(Serialize, Deserialize)]
+pub struct LogEntry {
+    /// Output samples since the log started when the event was sent
+    pub sample: u64,
+    #[serde(flatten)]
+    pub event: LoggedEvent,
+}
+
+/// Writes events to a JSON-lines file, flushing after every line so a
+/// crash loses nothing
+pub struct EventLog {
+    writer: LineWriter<File>,
+}
+
+impl EventLog {
+    /// Create a log at `path`, starting with a session line recording
+    /// `sample_rate`
+    pub fn create(path: impl AsRef<Path>, sample_rate: f32) -> io::Result<Self> {
+        let mut log = Self {
+            writer: LineWriter::new(File::create(path)?),
+        };
+        log.write(0, LoggedEvent::Session { sample_rate })?;
+        Ok(log)
+    }
+
+    pub fn write(&mut self, sample: u64, event: LoggedEvent) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &LogEntry { sample, event })?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+/// Read every entry of a log written by [`EventLog`]
+pub fn read_event_log(path: impl AsRef<Path>) -> io::Result<Vec<LogEntry>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control_channel;
+
+    #[test]
+    fn logs_read_back_with_their_timestamps() {
+        let path = std::env::temp_dir().join(format!("fm_synth_log_{}.jsonl", std::process::id()));
+        let (controller, queue) = control_channel(16);
+        queue.advance(1000);
+        controller.start_log(EventLog::create(&path, 44100.0).unwrap());
+        controller.note_on(60, 0.5);
+        queue.advance(256);
+        controller.pitch_bend(-0.25);
+        controller.schedule(2000, Command::NoteOff { note: 60 });
+        controller.set_params(FMParams { modulation_index: 3.0, ..FMParams::default() }).unwrap();
+        controller.stop_log();
+        controller.mod_wheel(1.0);
+
+        let entries = read_event_log(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let samples: Vec<u64> = entries.iter().map(|entry| entry.sample).collect();
+        // Scheduled commands keep their own time; the rest the time sent
+        assert_eq!(samples, [0, 0, 256, 1000, 256]);
+        assert!(matches!(entries[0].event, LoggedEvent::Session { sample_rate: 44100.0 }));
+        let commands: Vec<Command> = entries.iter().filter_map(|entry| entry.event.to_command()).collect();
+        assert!(matches!(commands[0], Command::NoteOn { note: 60, velocity: 0.5 }));
+        assert!(matches!(commands[1], Command::PitchBend(-0.25)));
+        assert!(matches!(commands[2], Command::NoteOff { note: 60 }));
+        assert!(matches!(&commands[3], Command::SetParams(params) if params.modulation_index == 3.0));
+        assert_eq!(commands.len(), 4);
+    }
+}
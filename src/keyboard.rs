@@ -0,0 +1,207 @@
+//! Playing the synth from the computer keyboard in a terminal
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use fm_synth::Controller;
+
+/// Keys of the computer keyboard's two piano rows, in semitones from the
+/// bottom C: Z to comma for the lower octave and Q to P for the upper,
+/// with L and period running on past the lower C as on trackers
+const PIANO_KEYS: [(u8, u8); 32] = [
+    (b'z', 0), (b's', 1), (b'x', 2), (b'd', 3), (b'c', 4), (b'v', 5), (b'g', 6),
+    (b'b', 7), (b'h', 8), (b'n', 9), (b'j', 10), (b'm', 11), (b',', 12), (b'l', 13),
+    (b'.', 14), (b'q', 12), (b'2', 13), (b'w', 14), (b'3', 15), (b'e', 16), (b'r', 17),
+    (b'5', 18), (b't', 19), (b'6', 20), (b'y', 21), (b'7', 22), (b'u', 23), (b'i', 24),
+    (b'9', 25), (b'o', 26), (b'0', 27), (b'p', 28),
+];
+
+/// Seconds a computer-keyboard note sounds for. Terminals report no key
+/// releases, so notes end on their own unless auto-repeat keeps them
+/// going; this outlasts the usual delay before repeat starts.
+const KEY_HOLD_SECONDS: f32 = 0.6;
+
+/// Velocity of computer-keyboard notes
+const KEY_VELOCITY: f32 = 0.8;
+
+/// How long after Escape the rest of an arrow key's sequence may take to
+/// arrive; terminals send it all at once, so a longer gap is a lone Escape
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(30);
+
+/// A key press read from the terminal
+#[derive(Clone, Copy, PartialEq)]
+pub enum Key {
+    /// A character key, lowercased
+    Char(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Shift-Tab
+    BackTab,
+    /// Escape pressed on its own
+    Escape,
+}
+
+/// Read key presses from stdin on a thread of their own, decoding the
+/// escape sequences terminals send for arrow keys
+pub fn read_keys() -> Receiver<Key> {
+    // Bytes come through a channel so the rest of an escape sequence can
+    // be waited for with a timeout
+    let (byte_sender, bytes) = mpsc::channel();
+    std::thread::spawn(move || {
+        for byte in std::io::stdin().lock().bytes().map_while(Result::ok) {
+            if byte_sender.send(byte).is_err() {
+                break;
+            }
+        }
+    });
+    let (sender, keys) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut next = None;
+        while let Some(byte) = next.take().or_else(|| bytes.recv().ok()) {
+            let key = match byte {
+                0x1B => match bytes.recv_timeout(ESCAPE_TIMEOUT) {
+                    Ok(b'[') => match read_sequence(&bytes) {
+                        Some(key) => key,
+                        None => continue,
+                    },
+                    // Escape, then a key of its own
+                    Ok(byte) => {
+                        next = Some(byte);
+                        Key::Escape
+                    }
+                    Err(_) => Key::Escape,
+                },
+                byte => Key::Char(byte.to_ascii_lowercase()),
+            };
+            if sender.send(key).is_err() {
+                break;
+            }
+        }
+    });
+    keys
+}
+
+/// Read the rest of a control sequence after its Escape and `[`: any
+/// parameter bytes, then the final byte naming the key. Sequences for
+/// other keys are read to the end and dropped.
+fn read_sequence(bytes: &Receiver<u8>) -> Option<Key> {
+    loop {
+        match bytes.recv_timeout(ESCAPE_TIMEOUT).ok()? {
+            0x30..=0x3F => continue,
+            b'A' => return Some(Key::Up),
+            b'B' => return Some(Key::Down),
+            b'C' => return Some(Key::Right),
+            b'D' => return Some(Key::Left),
+            b'Z' => return Some(Key::BackTab),
+            _ => return None,
+        }
+    }
+}
+
+/// Notes played from the piano rows, each ending a little after its key
+/// was last seen
+pub struct Keyboard {
+    octave: u8,
+    hold: u64,               // Samples a note sounds for
+    held: Vec<(u8, u64)>,    // Notes and the sample they end on
+}
+
+impl Keyboard {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            octave: 4,
+            hold: (KEY_HOLD_SECONDS * sample_rate) as u64,
+            held: Vec::new(),
+        }
+    }
+
+    pub fn octave(&self) -> u8 {
+        self.octave
+    }
+
+    /// Shift the bottom C by whole octaves, within the MIDI range
+    pub fn shift_octave(&mut self, up: bool) {
+        self.octave = if up { (self.octave + 1).min(8) } else { self.octave.saturating_sub(1) };
+    }
+
+    /// Play the note for `key` if it is a piano key, returning whether it was
+    pub fn press(&mut self, synth: &Controller, key: u8) -> bool {
+        let Some(&(_, offset)) = PIANO_KEYS.iter().find(|&&(k, _)| k == key) else {
+            return false;
+        };
+        let note = (12 * (self.octave + 1) + offset).min(127);
+        let now = synth.position();
+        match self.held.iter_mut().find(|(n, _)| *n == note) {
+            // Auto-repeat of a key already sounding just keeps it on
+            Some((_, until)) => *until = now + self.hold,
+            None => {
+                synth.note_on(note, KEY_VELOCITY);
+                self.held.push((note, now + self.hold));
+            }
+        }
+        true
+    }
+
+    /// Release notes whose keys haven't been seen for the hold time
+    pub fn release_due(&mut self, synth: &Controller) {
+        let now = synth.position();
+        self.held.retain(|&(note, until)| {
+            if until <= now {
+                synth.note_off(note);
+            }
+            until > now
+        });
+    }
+
+    /// Release every note
+    pub fn release_all(&mut self, synth: &Controller) {
+        synth.all_notes_off();
+        self.held.clear();
+    }
+}
+
+/// Unbuffered, unechoed terminal input, restored on drop. This uses
+/// `stty`, so it needs a Unix terminal.
+pub struct RawTerminal {
+    saved: String,  // `stty -g` settings from before
+}
+
+impl RawTerminal {
+    pub fn enter() -> anyhow::Result<Self> {
+        let output = Command::new("stty").arg("-g").stdin(Stdio::inherit()).output()?;
+        if !output.status.success() {
+            anyhow::bail!("Keyboard play needs a terminal");
+        }
+        Command::new("stty").args(["-icanon", "-echo", "min", "1"]).status()?;
+        Ok(Self { saved: String::from_utf8_lossy(&output.stdout).trim().to_string() })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = Command::new("stty").arg(&self.saved).status();
+    }
+}
+
+/// The whole terminal drawn on with the cursor hidden, cleared and given
+/// its cursor back on drop, however the drawing ends
+pub struct FullScreen;
+
+impl FullScreen {
+    pub fn enter() -> Self {
+        print!("\x1b[?25l");
+        let _ = std::io::stdout().flush();
+        Self
+    }
+}
+
+impl Drop for FullScreen {
+    fn drop(&mut self) {
+        print!("\x1b[H\x1b[J\x1b[?25h");
+        let _ = std::io::stdout().flush();
+    }
+}
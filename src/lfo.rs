@@ -0,0 +1,79 @@
+//! Low-frequency oscillator for vibrato, tremolo and timbre movement
+
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// What an [`Lfo`] modulates
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LfoDestination {
+    /// Vibrato of up to a semitone either way
+    Pitch,
+    /// Tremolo dipping by up to the full level
+    Amplitude,
+    /// Modulation index swung between none and double
+    ModIndex,
+}
+
+impl LfoDestination {
+    pub const ALL: [LfoDestination; 3] = [
+        LfoDestination::Pitch,
+        LfoDestination::Amplitude,
+        LfoDestination::ModIndex,
+    ];
+
+    /// Names in [`ALL`](Self::ALL) order, as shown by the parameter table
+    pub const NAMES: &'static [&'static str] = &["pitch", "amplitude", "index"];
+
+    /// Look up by position in [`ALL`](Self::ALL)
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Sine LFO restarted at each note-on
+pub struct Lfo {
+    sample_rate: f32,
+    phase: f32,  // In cycles (0.0 - 1.0)
+    rate: f32,   // Hz
+    depth: f32,  // 0.0 - 1.0
+}
+
+impl Lfo {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            phase: 0.0,
+            rate: 5.0,
+            depth: 0.0,
+        }
+    }
+
+    /// Set rate in Hz and depth (0.0 - 1.0)
+    pub fn set(&mut self, rate: f32, depth: f32) {
+        self.rate = rate;
+        self.depth = depth;
+    }
+
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    /// Advance by one sample and return the output, between -depth and depth
+    pub fn process(&mut self) -> f32 {
+        let out = (2.0 * PI * self.phase).sin() * self.depth;
+        self.phase += self.rate / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+        }
+        out
+    }
+}
@@ -0,0 +1,80 @@
+//! A small FM synthesis engine.
+//!
+//! [`FMSynth`] is a polyphonic synth whose voices each combine a
+//! four-operator [`FMOscillator`], routed by one of the classic
+//! [`ALGORITHMS`], with an [`Envelope`] on every operator, a timbre
+//! envelope on the modulation index and an [`Lfo`] for vibrato, tremolo or
+//! timbre movement. Each operator takes its modulation as phase
+//! modulation or exponential FM, by its [`ModulationInput`]. The synth
+//! renders mono or stereo blocks, or single samples, optionally handing
+//! each voice's output to the host before mixing as a [`VoiceOutput`], and
+//! has no audio backend of its own, so it can be driven from any output
+//! (the bundled binary uses cpal). Voices can run oversampled, with a
+//! [`Decimator`] filtering them back to the output rate.
+//!
+//! Externally editable values live in [`FMParams`]; [`PARAMETERS`] describes
+//! their ranges and display units so frontends can format, parse and
+//! validate them consistently. A [`Preset`] names a patch and saves it to
+//! or loads it from a JSON file; DX7 SysEx dumps can be imported with
+//! [`load_syx`].
+//!
+//! Raw MIDI bytes decode into [`MidiMessage`]s, which
+//! [`FMSynth::handle_midi`] turns into notes,
+//! pitched by a [`Tuning`] such as one of the built-in [`Temperament`]s.
+//! [`parse_osc`] decodes Open Sound Control packets for frontends that
+//! drive the synth over the network instead.
+//!
+//! Running on an audio thread, the synth is driven through a lock-free
+//! [`control_channel`]: a [`Controller`] queues [`Command`]s from other
+//! threads and the audio callback applies them from its [`CommandQueue`],
+//! either at the next block or, when scheduled, on an exact sample.
+//! The controller can record what it sends to an [`EventLog`] for later
+//! analysis or replay. A [`BlockRenderer`] keeps the callback on time when
+//! rendering falls behind, by the chosen [`OverrunPolicy`].
+//!
+//! [`CaptureBuffer`] keeps a rolling window of recent output that can be
+//! saved with [`write_wav`]. [`peak`], [`rms`] and [`octave_bands`] measure
+//! renders, e.g. to check whether a change altered the sound, and
+//! [`sidebands`] predicts the spectrum of simple FM before it is played.
+
+mod algorithm;
+mod analysis;
+mod capture;
+mod control;
+mod decimator;
+mod dx7;
+mod envelope;
+mod event_log;
+mod lfo;
+mod midi;
+mod osc;
+mod oscillator;
+mod overrun;
+mod params;
+mod preset;
+mod synth;
+mod tuning;
+mod voice;
+mod wav;
+
+pub use algorithm::{ALGORITHMS, Algorithm};
+pub use analysis::{octave_bands, peak, rms, sidebands, to_db};
+pub use capture::CaptureBuffer;
+pub use control::{Command, CommandQueue, Controller, Retired, control_channel};
+pub use decimator::{Decimator, MAX_OVERSAMPLING};
+pub use dx7::{DX7_OPERATORS, Dx7Operator, Dx7Voice, SysexError, load_syx, parse_sysex};
+pub use envelope::{Envelope, EnvelopeCurve, EnvelopeLoop};
+pub use event_log::{EventLog, LogEntry, LoggedEvent, read_event_log};
+pub use lfo::{Lfo, LfoDestination};
+pub use midi::{CC_MOD_WHEEL, MidiMessage, bend_amount, freq_to_note, note_to_freq};
+pub use osc::{OscArg, OscMessage, parse_osc};
+pub use oscillator::{FMOscillator, ModulationInput};
+pub use overrun::{BlockRenderer, OverrunPolicy};
+pub use params::{
+    FMParams, KeyZone, NUM_OPERATORS, OperatorParams, PARAMETERS, ParamError, Parameter,
+    SNAP_RATIOS, Unit,
+};
+pub use preset::{Preset, PresetError, TRIM_TARGET_DB};
+pub use synth::{DEFAULT_MAX_VOICES, FMSynth, StealPolicy, VoiceOutput};
+pub use tuning::{KeyedTuning, Temperament, Tuning};
+pub use wav::write_wav;
@@ -1,205 +1,180 @@
-use std::f32::consts::PI;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+mod editor;
+mod keyboard;
 
-// Add these to your Cargo.toml:
-// [dependencies]
-// cpal = "0.15"
-// anyhow = "1.0"
+use std::io::IsTerminal;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 
-/// FM Synthesizer parameters
-#[derive(Clone)]
-struct FMParams {
-    carrier_freq: f32,      // Carrier frequency in Hz
-    modulator_freq: f32,    // Modulator frequency in Hz
-    modulation_index: f32,  // Modulation depth
-    amplitude: f32,         // Output amplitude (0.0 - 1.0)
-}
+use keyboard::{Key, Keyboard, RawTerminal, read_keys};
 
-impl Default for FMParams {
-    fn default() -> Self {
-        Self {
-            carrier_freq: 440.0,
-            modulator_freq: 220.0,
-            modulation_index: 2.0,
-            amplitude: 0.3,
-        }
-    }
-}
+use fm_synth::{
+    Algorithm, BlockRenderer, CaptureBuffer, Command, Controller, DEFAULT_MAX_VOICES, EnvelopeCurve,
+    EventLog, FMParams, FMSynth, KeyedTuning, LfoDestination, LoggedEvent, MidiMessage,
+    NUM_OPERATORS, OperatorParams, OscArg, OscMessage, OverrunPolicy, ParamError, Parameter, Preset,
+    StealPolicy, Temperament, TRIM_TARGET_DB, control_channel, freq_to_note, load_syx, note_to_freq,
+    octave_bands, parse_osc, peak, read_event_log, rms, sidebands, to_db, write_wav,
+};
 
-/// FM Synthesizer oscillator
-struct FMOscillator {
-    sample_rate: f32,
-    carrier_phase: f32,
-    modulator_phase: f32,
-    params: FMParams,
-}
+/// UDP port the OSC server listens on unless one is given
+const DEFAULT_OSC_PORT: u16 = 9000;
 
-impl FMOscillator {
-    fn new(sample_rate: f32, params: FMParams) -> Self {
-        Self {
-            sample_rate,
-            carrier_phase: 0.0,
-            modulator_phase: 0.0,
-            params,
-        }
-    }
+/// Address the OSC server listens on unless `listen=` gives one. OSC has
+/// no authentication and `/load` opens files, so other machines are only
+/// let in on request.
+const DEFAULT_OSC_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
 
-    /// Generate next sample using FM synthesis
-    fn next_sample(&mut self) -> f32 {
-        // Calculate modulator output
-        let modulator = (2.0 * PI * self.modulator_phase).sin();
-        
-        // Apply modulation to carrier frequency
-        let modulated_freq = self.params.carrier_freq * 
-            (1.0 + self.params.modulation_index * modulator);
-        
-        // Generate carrier with modulated frequency
-        let carrier = (2.0 * PI * self.carrier_phase).sin();
-        
-        // Update phases
-        self.carrier_phase += modulated_freq / self.sample_rate;
-        self.modulator_phase += self.params.modulator_freq / self.sample_rate;
-        
-        // Wrap phases to prevent overflow
-        if self.carrier_phase >= 1.0 {
-            self.carrier_phase -= 1.0;
-        }
-        if self.modulator_phase >= 1.0 {
-            self.modulator_phase -= 1.0;
-        }
-        
-        // Return amplitude-scaled output
-        carrier * self.params.amplitude
-    }
+/// Seconds of output kept for retroactive capture
+const CAPTURE_SECONDS: f32 = 60.0;
 
-    fn set_params(&mut self, params: FMParams) {
-        self.params = params;
-    }
-}
+/// Commands that may be waiting for the audio thread
+const COMMAND_CAPACITY: usize = 1024;
 
-/// ADSR Envelope generator
-struct Envelope {
-    attack: f32,   // Attack time in seconds
-    decay: f32,    // Decay time in seconds
-    sustain: f32,  // Sustain level (0.0 - 1.0)
-    release: f32,  // Release time in seconds
-    
-    sample_rate: f32,
-    state: EnvelopeState,
-    level: f32,
-    time: f32,
-}
+/// Frames rendered per call to the synth
+const BLOCK_FRAMES: usize = 256;
 
-#[derive(PartialEq)]
-enum EnvelopeState {
-    Idle,
-    Attack,
-    Decay,
-    Sustain,
-    Release,
-}
+/// How far ahead of the output the live demo schedules its steps
+const SCHEDULE_AHEAD_SECONDS: f32 = 0.05;
 
-impl Envelope {
-    fn new(sample_rate: f32) -> Self {
-        Self {
-            attack: 0.01,
-            decay: 0.1,
-            sustain: 0.7,
-            release: 0.5,
-            sample_rate,
-            state: EnvelopeState::Idle,
-            level: 0.0,
-            time: 0.0,
-        }
-    }
+/// Sample rate of offline renders
+const RENDER_SAMPLE_RATE: u32 = 48000;
 
-    fn trigger(&mut self) {
-        self.state = EnvelopeState::Attack;
-        self.time = 0.0;
-    }
+/// Silence rendered after the last step so release tails can finish
+const RENDER_TAIL_SECONDS: f32 = 2.0;
 
-    fn release(&mut self) {
-        if self.state != EnvelopeState::Idle {
-            self.state = EnvelopeState::Release;
-            self.time = 0.0;
+fn main() -> anyhow::Result<()> {
+    // With no mode given, a patch editor opens in the terminal, playable from
+    // the computer keyboard; `presets` plays the preset demo instead, as
+    // happens when there is no terminal or it can't be read raw, e.g. on Windows.
+    // `midi [PORT]` plays from a MIDI keyboard, `osc [PORT]` takes Open Sound
+    // Control messages over UDP, `keys` plays from the computer keyboard
+    // without the editor, `demo` runs the annotated teaching demo, `render
+    // FILE` writes the preset demo to a WAV file without opening an audio
+    // device, `compare A B` reports how two presets differ and `replay LOG
+    // FILE` renders a logged performance, `preview` prints the predicted
+    // spectrum of the patch and `trim PRESET...` levels presets against each
+    // other. `voices=N` sets the global voice limit,
+    // `steal=oldest|quietest|same|none` how busy voices are taken over,
+    // `oversample=1|2|4` how many times the output rate voices render at to
+    // reduce aliasing, `crossfade=MS` how long held notes take to morph into a
+    // newly loaded preset, `overrun=drop|repeat|fade` what happens when
+    // rendering can't keep up with the device and `listen=ADDRESS` the address
+    // OSC is taken on, e.g. `listen=0.0.0.0` for every network interface rather
+    // than only this machine. Remaining arguments override parameters, e.g.
+    // `modulation_index=4 amplitude=-12dB`
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let max_voices = match args.iter().position(|arg| arg.starts_with("voices=")) {
+        Some(index) => args.remove(index)["voices=".len()..].parse::<usize>()?,
+        None => DEFAULT_MAX_VOICES,
+    };
+    let steal = match args.iter().position(|arg| arg.starts_with("steal=")) {
+        Some(index) => {
+            let name = &args.remove(index)["steal=".len()..];
+            StealPolicy::from_name(name).ok_or_else(|| {
+                anyhow::anyhow!("Unknown steal policy '{}' (oldest, quietest, same or none)", name)
+            })?
+        }
+        None => StealPolicy::Oldest,
+    };
+    let oversampling = match args.iter().position(|arg| arg.starts_with("oversample=")) {
+        Some(index) => match args.remove(index)["oversample=".len()..].parse::<usize>()? {
+            factor @ (1 | 2 | 4) => factor,
+            factor => anyhow::bail!("Unsupported oversampling {}x (1, 2 or 4)", factor),
+        },
+        None => 1,
+    };
+    let switch_time = match args.iter().position(|arg| arg.starts_with("crossfade=")) {
+        Some(index) => args.remove(index)["crossfade=".len()..].parse::<f32>()? / 1000.0,
+        None => 0.0,
+    };
+    let voicing = Voicing { max_voices, steal, oversampling, switch_time };
+    let overrun = match args.iter().position(|arg| arg.starts_with("overrun=")) {
+        Some(index) => {
+            let name = &args.remove(index)["overrun=".len()..];
+            OverrunPolicy::from_name(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown overrun policy '{}' (drop, repeat or fade)", name))?
         }
+        None => OverrunPolicy::DropVoices,
+    };
+    let osc_address = match args.iter().position(|arg| arg.starts_with("listen=")) {
+        Some(index) => args.remove(index)["listen=".len()..].parse::<IpAddr>()?,
+        None => DEFAULT_OSC_ADDRESS,
+    };
+    let midi_port = if args.first().is_some_and(|arg| arg == "midi") {
+        args.remove(0);
+        let has_port = args.first().is_some_and(|arg| !arg.contains('='));
+        Some(if has_port { args.remove(0) } else { String::new() })
+    } else {
+        None
+    };
+    let osc_port = if args.first().is_some_and(|arg| arg == "osc") {
+        args.remove(0);
+        let has_port = args.first().is_some_and(|arg| !arg.contains('='));
+        Some(if has_port { args.remove(0).parse::<u16>()? } else { DEFAULT_OSC_PORT })
+    } else {
+        None
+    };
+    let keys = args.first().is_some_and(|arg| arg == "keys");
+    if keys {
+        args.remove(0);
     }
-
-    fn process(&mut self) -> f32 {
-        let dt = 1.0 / self.sample_rate;
-        
-        match self.state {
-            EnvelopeState::Idle => {
-                self.level = 0.0;
-            }
-            EnvelopeState::Attack => {
-                self.level = self.time / self.attack;
-                if self.time >= self.attack {
-                    self.state = EnvelopeState::Decay;
-                    self.time = 0.0;
-                }
-            }
-            EnvelopeState::Decay => {
-                self.level = 1.0 - ((1.0 - self.sustain) * (self.time / self.decay));
-                if self.time >= self.decay {
-                    self.state = EnvelopeState::Sustain;
-                    self.time = 0.0;
-                }
-            }
-            EnvelopeState::Sustain => {
-                self.level = self.sustain;
-            }
-            EnvelopeState::Release => {
-                self.level = self.sustain * (1.0 - (self.time / self.release));
-                if self.time >= self.release {
-                    self.state = EnvelopeState::Idle;
-                    self.level = 0.0;
-                }
-            }
+    if args.first().is_some_and(|arg| arg == "compare") {
+        if args.len() < 3 {
+            anyhow::bail!("Usage: compare PRESET PRESET [voices=N] [id=value...]");
         }
-        
-        self.time += dt;
-        self.level
+        let (a, b) = (args.remove(1), args.remove(1));
+        let overrides = parse_overrides(args.into_iter().skip(1))?;
+        return compare_presets(&a, &b, &overrides, voicing);
     }
-}
-
-/// FM Synthesizer with envelope
-struct FMSynth {
-    oscillator: FMOscillator,
-    envelope: Envelope,
-}
-
-impl FMSynth {
-    fn new(sample_rate: f32, params: FMParams) -> Self {
-        Self {
-            oscillator: FMOscillator::new(sample_rate, params),
-            envelope: Envelope::new(sample_rate),
+    if args.first().is_some_and(|arg| arg == "trim") {
+        if args.len() < 2 {
+            anyhow::bail!("Usage: trim PRESET...");
         }
+        return trim_presets(&args[1..]);
     }
-
-    fn next_sample(&mut self) -> f32 {
-        let osc_out = self.oscillator.next_sample();
-        let env_out = self.envelope.process();
-        osc_out * env_out
+    if args.first().is_some_and(|arg| arg == "preview") {
+        let mut params = FMParams::default();
+        apply_overrides(&mut params, &parse_overrides(args.into_iter().skip(1))?)?;
+        preview_sidebands(&params);
+        return Ok(());
     }
-
-    fn note_on(&mut self) {
-        self.envelope.trigger();
+    if args.first().is_some_and(|arg| arg == "replay") {
+        if args.len() != 3 {
+            anyhow::bail!("Usage: replay LOG.jsonl FILE.wav [voices=N]");
+        }
+        let steps = replay_steps(&args[1])?;
+        return render_offline(steps, &args[2], voicing);
     }
-
-    fn note_off(&mut self) {
-        self.envelope.release();
+    let teaching = args.first().is_some_and(|arg| arg == "demo");
+    if teaching {
+        args.remove(0);
     }
-
-    fn set_params(&mut self, params: FMParams) {
-        self.oscillator.set_params(params);
+    let preset_demo = args.first().is_some_and(|arg| arg == "presets");
+    if preset_demo {
+        args.remove(0);
     }
-}
-
-fn main() -> anyhow::Result<()> {
+    let render_path = if args.first().is_some_and(|arg| arg == "render") {
+        args.remove(0);
+        if args.first().is_none_or(|arg| arg.contains('=')) {
+            anyhow::bail!("Usage: render FILE.wav [voices=N] [id=value...]");
+        }
+        Some(args.remove(0))
+    } else {
+        None
+    };
+    let overrides = parse_overrides(args.into_iter())?;
+    
+    // Choose demo mode: 1 for presets, 2 for melody, 3 for the teaching demo
+    let demo_mode = if teaching { 3 } else { 1 }; // Change this to switch between demos
+    
+    if let Some(path) = render_path {
+        let steps = demo_steps(demo_mode, &overrides)?;
+        return render_offline(steps, &path, voicing);
+    }
+    
     // Initialize audio
     let host = cpal::default_host();
     let device = host.default_output_device()
@@ -207,22 +182,51 @@ fn main() -> anyhow::Result<()> {
     
     let config = device.default_output_config()?;
     let sample_rate = config.sample_rate().0 as f32;
+    let channels = config.channels() as usize;
     
     // Create synth with default parameters
     let params = FMParams::default();
-    let synth = Arc::new(Mutex::new(FMSynth::new(sample_rate, params)));
+    let mut synth = FMSynth::new(sample_rate, params)?;
+    voicing.apply(&mut synth);
+    synth.warm_up();
+    let mut renderer = BlockRenderer::new(sample_rate, BLOCK_FRAMES, overrun);
     
-    // Clone for audio callback
-    let synth_clone = Arc::clone(&synth);
+    // The audio callback owns the synth; everything else talks to it
+    // through the controller, so rendering never waits on a lock
+    let (synth_control, mut commands) = control_channel(COMMAND_CAPACITY);
+    
+    // Rolling record of everything played, for retroactive capture
+    let capture = Arc::new(CaptureBuffer::new(sample_rate, CAPTURE_SECONDS));
+    let capture_clone = Arc::clone(&capture);
     
     // Build output stream
     let stream = match config.sample_format() {
         cpal::SampleFormat::F32 => device.build_output_stream(
             &config.into(),
             move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let mut synth = synth_clone.lock().unwrap();
-                for sample in data.iter_mut() {
-                    *sample = synth.next_sample();
+                // Rendering must finish within the time the buffer plays for
+                let buffer_frames = data.len() / channels;
+                let deadline = Instant::now()
+                    + Duration::from_secs_f32(buffer_frames as f32 / sample_rate);
+                // Render stereo blocks, split wherever a scheduled command
+                // falls: left and right go to the first two channels, and
+                // the mono mix to mono devices, any further channels and
+                // the capture buffer
+                let mut left = [0.0; BLOCK_FRAMES];
+                let mut right = [0.0; BLOCK_FRAMES];
+                for chunk in data.chunks_mut(channels * BLOCK_FRAMES) {
+                    let frames = chunk.len() / channels;
+                    commands.render(&mut synth, &mut left[..frames], &mut right[..frames], |synth, l, r| {
+                        renderer.render(synth, l, r, deadline);
+                    });
+                    for (i, frame) in chunk.chunks_mut(channels).enumerate() {
+                        let mono = (left[i] + right[i]) * 0.5;
+                        capture_clone.push(mono);
+                        frame.fill(mono);
+                        if let [l, r, ..] = frame {
+                            (*l, *r) = (left[i], right[i]);
+                        }
+                    }
                 }
             },
             |err| eprintln!("Error in audio stream: {}", err),
@@ -233,122 +237,1089 @@ fn main() -> anyhow::Result<()> {
     
     stream.play()?;
     
-    println!("FM Synthesizer Demo");
-    println!("==================");
+    if let Some(port) = midi_port {
+        return run_midi(&synth_control, &capture, &port, &overrides);
+    }
+    if let Some(port) = osc_port {
+        return run_osc(&synth_control, (osc_address, port), &overrides);
+    }
+    if keys {
+        return run_keys(&synth_control, sample_rate, &overrides);
+    }
+    // Without a terminal to edit in, play the preset demo instead
+    if !teaching && !preset_demo && std::io::stdin().is_terminal() {
+        match RawTerminal::enter() {
+            Ok(terminal) => {
+                let presets = live_presets(&overrides)?;
+                return editor::run_editor(&synth_control, terminal, sample_rate, presets);
+            }
+            Err(err) => eprintln!("No patch editor ({}); playing the preset demo", err),
+        }
+    }
+    
+    // Steps are scheduled a little ahead of the output, so they land on
+    // their exact samples however late the sleeps between them wake
+    let ahead = (SCHEDULE_AHEAD_SECONDS * sample_rate) as u64;
+    let mut at = synth_control.position() + ahead;
+    for step in demo_steps(demo_mode, &overrides)? {
+        match step {
+            Step::Print(text) => println!("{}", text),
+            Step::Send(command) => synth_control.schedule(at, command),
+            Step::Wait(seconds) => {
+                at += (seconds * sample_rate).round() as u64;
+                let due = at.saturating_sub(synth_control.position() + ahead);
+                std::thread::sleep(Duration::from_secs_f32(due as f32 / sample_rate));
+            }
+        }
+    }
     
-    // Choose demo mode: 1 for presets, 2 for melody
-    let demo_mode = 1; // Change this to switch between demos
+    println!("\nDone!");
+    Ok(())
+}
+
+/// Voice pool settings from the command line, shared by live and offline
+/// synths
+#[derive(Clone, Copy)]
+struct Voicing {
+    max_voices: usize,
+    steal: StealPolicy,
+    oversampling: usize,
+    switch_time: f32,  // Seconds
+}
+
+impl Voicing {
+    fn apply(self, synth: &mut FMSynth) {
+        synth.set_max_voices(self.max_voices);
+        synth.set_steal_policy(self.steal);
+        synth.set_oversampling(self.oversampling);
+        synth.set_switch_time(self.switch_time);
+    }
+}
+
+/// One step of a scripted performance, played live or rendered offline
+enum Step {
+    Print(String),
+    Send(Command),
+    /// Pause, in seconds
+    Wait(f32),
+}
+
+/// Script one of the demos with `overrides` applied to every patch
+fn demo_steps(
+    demo_mode: u32,
+    overrides: &[(&'static Parameter, f32)],
+) -> Result<Vec<Step>, ParamError> {
+    let mut steps = vec![
+        Step::Print("FM Synthesizer Demo".to_string()),
+        Step::Print("==================".to_string()),
+    ];
+    
+    for &(param, value) in overrides {
+        steps.push(Step::Print(format!("Override {}: {} (default {})",
+                   param.name, param.format(value), param.format(param.default_value()))));
+    }
     
     match demo_mode {
         1 => {
             // Demo 1: Play through all presets
-            println!("Playing preset sounds...\n");
+            steps.push(Step::Print("Playing preset sounds...\n".to_string()));
             
             let presets = example_presets();
-            let note_freqs = vec![220.0, 440.0, 330.0, 440.0]; // A3, A4, E4, A4
-            
             for (name, mut preset_params) in presets {
-                println!("Preset: {}", name);
+                steps.push(Step::Print(format!("Preset: {} ({})",
+                           name, describe(&preset_params, &["algorithm"]))));
                 
-                for &freq in &note_freqs {
-                    // Scale frequencies proportionally
-                    let freq_ratio = freq / 440.0;
-                    preset_params.carrier_freq *= freq_ratio;
-                    preset_params.modulator_freq *= freq_ratio;
-                    
-                    println!("  Note at {:.1}Hz", preset_params.carrier_freq);
-                    
-                    {
-                        let mut synth = synth.lock().unwrap();
-                        synth.set_params(preset_params.clone());
-                        synth.note_on();
-                    }
-                    
-                    std::thread::sleep(Duration::from_millis(600));
-                    
-                    {
-                        let mut synth = synth.lock().unwrap();
-                        synth.note_off();
-                    }
-                    
-                    std::thread::sleep(Duration::from_millis(200));
-                }
+                apply_overrides(&mut preset_params, overrides)?;
+                preset_params.validate()?;
+                let base_note = freq_to_note(preset_params.frequency).round() as i32;
+                // Release tails morph into the next preset with crossfade=MS
+                steps.push(Step::Send(Command::SwitchParams(Arc::new(preset_params))));
+                audition_steps(&mut steps, base_note);
                 
-                println!();
-                std::thread::sleep(Duration::from_millis(500));
+                steps.push(Step::Print(String::new()));
+                steps.push(Step::Wait(0.5));
             }
         }
         2 => {
             // Demo 2: Play a melody with custom parameters
-            println!("Playing a sequence of FM tones...\n");
+            steps.push(Step::Print("Playing a sequence of FM tones...\n".to_string()));
             
-            let notes = vec![
-                (440.0, 880.0, 2.0),   // A4 with 2:1 ratio
-                (523.25, 1046.5, 3.0), // C5 with 2:1 ratio
-                (659.25, 659.25, 5.0), // E5 with 1:1 ratio (bell-like)
-                (440.0, 220.0, 8.0),   // A4 with 1:2 ratio (sub-harmonic)
+            let notes = [
+                (69, 2.0, 2.0), // A4 with 2:1 ratio
+                (72, 2.0, 3.0), // C5 with 2:1 ratio
+                (76, 1.0, 5.0), // E5 with 1:1 ratio (bell-like)
+                (69, 0.5, 8.0), // A4 with 1:2 ratio (sub-harmonic)
             ];
             
-            for (carrier, modulator, mod_index) in notes {
-                println!("Playing: Carrier={:.1}Hz, Modulator={:.1}Hz, Index={:.1}", 
-                         carrier, modulator, mod_index);
+            for (note, ratio, mod_index) in notes {
+                let mut note_params = FMParams {
+                    operators: stack(&[(1.0, 1.0), (ratio, 1.0)]),
+                    modulation_index: mod_index,
+                    amplitude: 0.3,
+                    ..FMParams::default()
+                };
+                apply_overrides(&mut note_params, overrides)?;
+                note_params.validate()?;
+                steps.push(Step::Print(format!("Playing: Note {}, {}", note,
+                           describe(&note_params, &["op2_ratio", "modulation_index"]))));
                 
-                {
-                    let mut synth = synth.lock().unwrap();
-                    synth.set_params(FMParams {
-                        carrier_freq: carrier,
-                        modulator_freq: modulator,
-                        modulation_index: mod_index,
+                steps.push(Step::Send(Command::SetParams(Arc::new(note_params))));
+                steps.push(Step::Send(Command::NoteOn { note, velocity: 1.0 }));
+                steps.push(Step::Wait(0.8));
+                
+                steps.push(Step::Send(Command::NoteOff { note }));
+                steps.push(Step::Wait(0.7));
+            }
+        }
+        3 => {
+            // Demo 3: Explain FM one idea at a time
+            steps.push(Step::Print("An introduction to FM synthesis\n".to_string()));
+            for lesson in lessons() {
+                lesson_steps(&mut steps, lesson, overrides)?;
+            }
+        }
+        _ => {
+            steps.push(Step::Print("Invalid demo mode".to_string()));
+        }
+    }
+    
+    Ok(steps)
+}
+
+/// One topic of the teaching demo: a held note heard through a series of
+/// patches that differ in the parameters being taught
+struct Lesson {
+    title: &'static str,
+    explanation: &'static str,
+    /// Parameters the patches vary, printed for each
+    ids: &'static [&'static str],
+    note: u8,
+    /// Each patch with a remark on what to listen for
+    patches: Vec<(FMParams, &'static str)>,
+}
+
+/// Seconds each patch of a lesson is heard for
+const LESSON_PATCH_SECONDS: f32 = 1.5;
+
+/// Append `lesson`, with `overrides` applied to every patch
+fn lesson_steps(
+    steps: &mut Vec<Step>,
+    lesson: Lesson,
+    overrides: &[(&'static Parameter, f32)],
+) -> Result<(), ParamError> {
+    steps.push(Step::Print(lesson.title.to_string()));
+    steps.push(Step::Print(lesson.explanation.to_string()));
+    
+    for (index, (mut params, remark)) in lesson.patches.into_iter().enumerate() {
+        apply_overrides(&mut params, overrides)?;
+        params.validate()?;
+        let routing = Algorithm::get(params.algorithm).map_or("", |algorithm| algorithm.name);
+        let values = describe(&params, lesson.ids);
+        steps.push(Step::Print(if lesson.ids.contains(&"algorithm") {
+            format!("  {} ({}): {}", values, routing, remark)
+        } else {
+            format!("  {}: {}", values, remark)
+        }));
+        
+        // Change the patch under the held note so only the lesson's
+        // parameters move
+        steps.push(Step::Send(Command::SetParams(Arc::new(params))));
+        if index == 0 {
+            steps.push(Step::Send(Command::NoteOn { note: lesson.note, velocity: 1.0 }));
+        }
+        steps.push(Step::Wait(LESSON_PATCH_SECONDS));
+    }
+    
+    steps.push(Step::Send(Command::NoteOff { note: lesson.note }));
+    steps.push(Step::Print(String::new()));
+    steps.push(Step::Wait(1.0));
+    Ok(())
+}
+
+/// The teaching demo's lessons, in order
+fn lessons() -> Vec<Lesson> {
+    // Two-operator patch held long enough to hear each change
+    let two_op = |ratio: f32, index: f32| FMParams {
+        operators: {
+            let mut ops = stack(&[(1.0, 1.0), (ratio, 1.0)]);
+            set_envelope(&mut ops[0], 0.01, 0.1, 1.0, 0.5);
+            set_envelope(&mut ops[1], 0.01, 0.1, 1.0, 0.5);
+            ops
+        },
+        modulation_index: index,
+        amplitude: 0.3,
+        ..FMParams::default()
+    };
+    
+    vec![
+        Lesson {
+            title: "1. Modulation index",
+            explanation: "The modulator bends the carrier's phase, and the index sets how far. \
+                At 0 the carrier is a plain sine; as the index rises, energy spreads into \
+                sidebands either side of the carrier and the tone brightens.",
+            ids: &["modulation_index"],
+            note: 57,
+            patches: vec![
+                (two_op(1.0, 0.0), "a pure sine"),
+                (two_op(1.0, 0.5), "a little warmth from the first sidebands"),
+                (two_op(1.0, 1.5), "brighter, like a reed"),
+                (two_op(1.0, 3.0), "buzzy, many harmonics"),
+                (two_op(1.0, 6.0), "harsh: energy spread far up the spectrum"),
+            ],
+        },
+        Lesson {
+            title: "2. Frequency ratio",
+            explanation: "Sidebands fall at the carrier frequency plus and minus multiples of \
+                the modulator's, so the ratio between them picks the spectrum. Whole-number \
+                ratios are harmonic; irrational ones sound bell-like or metallic.",
+            ids: &["op2_ratio"],
+            note: 57,
+            patches: vec![
+                (two_op(1.0, 2.0), "every harmonic, like a sawtooth"),
+                (two_op(2.0, 2.0), "odd harmonics only, hollow like a square"),
+                (two_op(3.0, 2.0), "gaps in the harmonic series, nasal"),
+                (two_op(1.414, 2.0), "inharmonic: a bell"),
+                (two_op(3.5, 2.0), "inharmonic and higher: metallic"),
+            ],
+        },
+        Lesson {
+            title: "3. Feedback",
+            explanation: "An operator can modulate itself with its own output. Rising \
+                feedback turns a single sine into something close to a sawtooth, and at the \
+                top into noisy grit.",
+            ids: &["op1_feedback"],
+            note: 57,
+            patches: [0.0, 3.0, 5.0, 6.0, 7.0]
+                .into_iter()
+                .zip(["a pure sine", "slightly bright", "saw-like", "raspy", "breaking into noise"])
+                .map(|(feedback, remark)| {
+                    let mut params = two_op(1.0, 0.0);
+                    params.operators[0].feedback = feedback;
+                    (params, remark)
+                })
+                .collect(),
+        },
+        Lesson {
+            title: "4. Algorithms",
+            explanation: "The algorithm wires the operators together: which modulate which, \
+                and which are heard. The same four operators sound very different as one \
+                stack, as pairs, or all heard side by side, where FM becomes additive \
+                synthesis.",
+            ids: &["algorithm"],
+            note: 57,
+            patches: [(1, "one stack: bright and complex"), (5, "two pairs mixed"),
+                      (6, "one modulator shared by three carriers"),
+                      (8, "no modulation at all: four sines added like drawbars")]
+                .into_iter()
+                .map(|(algorithm, remark)| {
+                    let params = FMParams {
+                        operators: stack(&[(1.0, 1.0), (2.0, 0.8), (3.0, 0.6), (4.0, 0.4)]),
+                        algorithm,
+                        modulation_index: 1.5,
                         amplitude: 0.3,
-                    });
-                    synth.note_on();
+                        ..FMParams::default()
+                    };
+                    (params, remark)
+                })
+                .collect(),
+        },
+    ]
+}
+
+/// Append the audition phrase every preset is demonstrated with: A3, A4,
+/// E4, A4 relative to `base_note`, at varying velocities
+fn audition_steps(steps: &mut Vec<Step>, base_note: i32) {
+    let note_offsets = [-12, 0, -5, 0];
+    let velocities = [0.9, 0.5, 1.0, 0.3];
+    
+    for (&offset, &velocity) in note_offsets.iter().zip(&velocities) {
+        let note = (base_note + offset).clamp(0, 127) as u8;
+        steps.push(Step::Print(format!("  Note {} at {:.1} Hz, velocity {:.0}%",
+                   note, note_to_freq(note), velocity * 100.0)));
+        
+        steps.push(Step::Send(Command::NoteOn { note, velocity }));
+        steps.push(Step::Wait(0.6));
+        
+        steps.push(Step::Send(Command::NoteOff { note }));
+        steps.push(Step::Wait(0.2));
+    }
+}
+
+/// Play `steps` into a fresh synth as fast as possible, followed by a tail
+/// for the last release, and return the output
+fn render(steps: Vec<Step>, voicing: Voicing) -> Result<Vec<f32>, ParamError> {
+    let sample_rate = RENDER_SAMPLE_RATE as f32;
+    let mut synth = FMSynth::new(sample_rate, FMParams::default())?;
+    voicing.apply(&mut synth);
+    
+    let mut samples = Vec::new();
+    for step in steps.into_iter().chain([Step::Wait(RENDER_TAIL_SECONDS)]) {
+        match step {
+            Step::Print(text) => println!("{}", text),
+            // Offline there is no audio thread to keep from freeing
+            Step::Send(command) => drop(synth.apply(command)),
+            Step::Wait(seconds) => {
+                let start = samples.len();
+                samples.resize(start + (seconds * sample_rate).round() as usize, 0.0);
+                synth.process(&mut samples[start..]);
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// Render `steps` and write the result to a WAV file at `path`
+fn render_offline(steps: Vec<Step>, path: &str, voicing: Voicing) -> anyhow::Result<()> {
+    let samples = render(steps, voicing)?;
+    write_wav(path, &samples, RENDER_SAMPLE_RATE)?;
+    println!("Rendered {:.1}s to {}", samples.len() as f32 / RENDER_SAMPLE_RATE as f32, path);
+    Ok(())
+}
+
+/// Script the performance recorded in the event log at `path`, keeping each
+/// event's timing at the render sample rate
+fn replay_steps(path: &str) -> anyhow::Result<Vec<Step>> {
+    let entries = read_event_log(path)?;
+    let log_rate = match entries.first().map(|entry| &entry.event) {
+        Some(&LoggedEvent::Session { sample_rate }) => sample_rate,
+        _ => anyhow::bail!("{} is not an event log", path),
+    };
+    let scale = RENDER_SAMPLE_RATE as f32 / log_rate;
+    
+    let mut steps = Vec::new();
+    let mut position = 0;
+    for entry in &entries {
+        // Wait in whole samples so rounding never accumulates
+        let sample = (entry.sample as f32 * scale).round() as u64;
+        if sample > position {
+            steps.push(Step::Wait((sample - position) as f32 / RENDER_SAMPLE_RATE as f32));
+            position = sample;
+        }
+        if let Some(command) = entry.event.to_command() {
+            if let Command::SetParams(params) | Command::SwitchParams(params) = &command {
+                params.validate()?;
+            }
+            steps.push(Step::Send(command));
+        }
+    }
+    println!("Replaying {} events ({:.1}s) from {}",
+             entries.len() - 1, position as f32 / RENDER_SAMPLE_RATE as f32, path);
+    Ok(steps)
+}
+
+/// Quietest sideband printed by [`preview_sidebands`], in dB
+const PREVIEW_FLOOR_DB: f32 = -60.0;
+
+/// Print the Bessel sideband spectrum of op 2 modulating op 1 in `params`,
+/// as a bar chart. Other operators and feedback are ignored, so this is
+/// exact only for two-operator patches.
+fn preview_sidebands(params: &FMParams) {
+    let [carrier, modulator, ..] = params.operators;
+    let index = params.modulation_index * modulator.level;
+    let frequency = params.frequency;
+    println!("Op 2 (ratio {:.2}) modulating op 1 (ratio {:.2}) at index {:.2}, note {:.1} Hz:",
+             modulator.ratio, carrier.ratio, index, frequency);
+    
+    let components = sidebands(carrier.ratio * frequency, modulator.ratio * frequency, index);
+    for (frequency, amplitude) in components {
+        let db = to_db(amplitude * carrier.level);
+        if db >= PREVIEW_FLOOR_DB {
+            let bar = "#".repeat(((db - PREVIEW_FLOOR_DB) / 2.0).round() as usize);
+            println!("  {:>9.1} Hz  {:6.1} dB  {}", frequency, db, bar);
+        }
+    }
+}
+
+/// Render the audition phrase on A4 through presets `a` and `b` (built-in
+/// names or JSON preset files) and report how far apart they are: levels,
+/// the null-test residual (B subtracted from A) and the difference per
+/// octave band
+fn compare_presets(
+    a: &str,
+    b: &str,
+    overrides: &[(&'static Parameter, f32)],
+    voicing: Voicing,
+) -> anyhow::Result<()> {
+    let render_preset = |name: &str| -> anyhow::Result<(String, Vec<f32>)> {
+        let Preset { name, mut params } = find_preset(name)?;
+        apply_overrides(&mut params, overrides)?;
+        params.validate()?;
+        
+        let mut steps = vec![Step::Send(Command::SetParams(Arc::new(params)))];
+        audition_steps(&mut steps, 69);
+        steps.retain(|step| !matches!(step, Step::Print(_)));
+        Ok((name, render(steps, voicing)?))
+    };
+    let (name_a, samples_a) = render_preset(a)?;
+    let (name_b, samples_b) = render_preset(b)?;
+    
+    let sample_rate = RENDER_SAMPLE_RATE as f32;
+    println!("Comparing {} and {} ({:.1}s)", name_a, name_b, samples_a.len() as f32 / sample_rate);
+    for (name, samples) in [(&name_a, &samples_a), (&name_b, &samples_b)] {
+        println!("  {:<16} peak {:6.1} dB   RMS {:6.1} dB",
+                 name, to_db(peak(samples)), to_db(rms(samples)));
+    }
+    
+    let residual: Vec<f32> = samples_a.iter().zip(&samples_b).map(|(a, b)| a - b).collect();
+    let residual_db = to_db(rms(&residual));
+    println!("  Null-test residual: RMS {:.1} dB ({:+.1} dB relative to {})",
+             residual_db, residual_db - to_db(rms(&samples_a)), name_a);
+    
+    println!("  Octave band difference ({} minus {}):", name_b, name_a);
+    let bands_a = octave_bands(&samples_a, sample_rate);
+    let bands_b = octave_bands(&samples_b, sample_rate);
+    for (&(centre, db_a), &(_, db_b)) in bands_a.iter().zip(&bands_b) {
+        println!("    {:>8} Hz  {:+6.1} dB", centre.round(), db_b - db_a);
+    }
+    Ok(())
+}
+
+/// Load a JSON preset file, or look up a built-in preset by name
+fn find_preset(name: &str) -> anyhow::Result<Preset> {
+    if name.ends_with(".json") {
+        return Ok(Preset::load(name)?);
+    }
+    example_presets().into_iter()
+        .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+        .map(|(name, params)| Preset::new(name, params))
+        .ok_or_else(|| anyhow::anyhow!("No preset named '{}'", name))
+}
+
+/// Measure the worst-case peak of each preset (built-in names or JSON
+/// preset files) and report the trim that levels it, saving the trim back
+/// into JSON files
+fn trim_presets(names: &[String]) -> anyhow::Result<()> {
+    println!("Trimming to {:.1} dBFS worst-case single-note peak", TRIM_TARGET_DB);
+    for name in names {
+        let mut preset = find_preset(name)?;
+        let worst = preset.auto_trim(RENDER_SAMPLE_RATE as f32)?;
+        println!("  {:<16} peak {:6.1} dB   trim {}", preset.name, to_db(worst),
+                 describe(&preset.params, &["trim"]).trim_start_matches("Trim="));
+        if name.ends_with(".json") {
+            preset.save(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Play the synth from a MIDI input while reading commands from stdin.
+/// `port` selects an input by index or name substring; empty picks the
+/// first one.
+fn run_midi(
+    synth: &Controller,
+    capture: &CaptureBuffer,
+    port: &str,
+    overrides: &[(&'static Parameter, f32)],
+) -> anyhow::Result<()> {
+    let mut params = FMParams::default();
+    apply_overrides(&mut params, overrides)?;
+    synth.set_params(params.clone())?;
+    
+    let midi_in = midir::MidiInput::new("fm_synth")?;
+    let ports = midi_in.ports();
+    let names: Vec<String> = ports.iter()
+        .map(|p| midi_in.port_name(p).unwrap_or_default())
+        .collect();
+    
+    let index = match port.parse::<usize>() {
+        Ok(index) => Some(index),
+        Err(_) if port.is_empty() => Some(0),
+        Err(_) => names.iter().position(|name| name.contains(port)),
+    };
+    let (selected, name) = index
+        .and_then(|i| Some((ports.get(i)?, names.get(i)?)))
+        .ok_or_else(|| anyhow::anyhow!("No MIDI input matching '{}' (found {:?})", port, names))?;
+    println!("Listening on MIDI input: {}", name);
+    
+    let midi_synth = synth.clone();
+    let _connection = midi_in.connect(
+        selected,
+        "fm_synth-input",
+        move |_timestamp, bytes, _| {
+            if let Some(message) = MidiMessage::parse(bytes) {
+                midi_synth.handle_midi(message);
+            }
+        },
+        (),
+    ).map_err(|err| anyhow::anyhow!("Failed to open MIDI input: {}", err))?;
+    
+    println!("Play some notes. Commands:");
+    println!("  capture [SECONDS]     save recent output to a WAV file");
+    println!("  tuning NAME [ROOT]    switch temperament (equal, just, meantone, pythagorean)");
+    println!("  copy FROM TO          copy one operator's settings onto another");
+    println!("  copyenv FROM          give every operator FROM's envelope");
+    println!("  save FILE [NAME]      save the current patch as a JSON preset");
+    println!("  load FILE             load a JSON preset");
+    println!("  trim                  level the patch to the auto-trim target");
+    println!("  syx FILE [N]          import voice N (default 1) of a DX7 SysEx dump");
+    println!("  log FILE|off          record every event to a JSON-lines log");
+    println!("  zone low|high FILE [SPLIT]  play a preset's operators beyond a split note");
+    println!("  zone low|high off     turn a key zone off");
+    println!("  Enter                 quit");
+    for line in std::io::stdin().lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("capture") => {
+                let seconds = words.next().map_or(Ok(30.0), str::parse::<f32>);
+                match seconds {
+                    Ok(seconds) => {
+                        if let Err(err) = save_capture(capture, seconds) {
+                            eprintln!("Capture failed: {}", err);
+                        }
+                    }
+                    Err(_) => eprintln!("Usage: capture [SECONDS]"),
                 }
-                
-                std::thread::sleep(Duration::from_millis(800));
-                
-                {
-                    let mut synth = synth.lock().unwrap();
-                    synth.note_off();
+            }
+            Some("tuning") => {
+                let temperament = words.next().and_then(Temperament::from_name);
+                let root = words.next().map_or(Some(0), parse_pitch_class);
+                match (temperament, root) {
+                    (Some(temperament), Some(root)) => {
+                        synth.set_tuning(KeyedTuning::new(temperament, root));
+                        println!("Tuning: {} on {}", temperament.name(), PITCH_CLASSES[root as usize]);
+                    }
+                    _ => eprintln!("Usage: tuning equal|just|meantone|pythagorean [ROOT]"),
                 }
-                
-                std::thread::sleep(Duration::from_millis(700));
             }
+            Some("copy") => {
+                let from = words.next().and_then(parse_operator);
+                let to = words.next().and_then(parse_operator);
+                match (from, to) {
+                    (Some(from), Some(to)) => {
+                        params.copy_operator(from, to);
+                        synth.set_params(params.clone())?;
+                        println!("Copied op {} to op {}", from + 1, to + 1);
+                    }
+                    _ => eprintln!("Usage: copy FROM TO (operators 1-{})", NUM_OPERATORS),
+                }
+            }
+            Some("copyenv") => match words.next().and_then(parse_operator) {
+                Some(from) => {
+                    params.copy_envelope_to_all(from);
+                    synth.set_params(params.clone())?;
+                    println!("Copied op {} envelope to all operators", from + 1);
+                }
+                None => eprintln!("Usage: copyenv FROM (operators 1-{})", NUM_OPERATORS),
+            },
+            Some("save") => match words.next() {
+                Some(path) => {
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    let name = if name.is_empty() { path.trim_end_matches(".json") } else { &name };
+                    match Preset::new(name, params.clone()).save(path) {
+                        Ok(()) => println!("Saved '{}' to {}", name, path),
+                        Err(err) => eprintln!("Save failed: {}", err),
+                    }
+                }
+                None => eprintln!("Usage: save FILE [NAME]"),
+            },
+            Some("load") => match words.next().map(Preset::load) {
+                Some(Ok(preset)) => {
+                    params = preset.params;
+                    synth.switch_params(params.clone())?;
+                    println!("Loaded '{}'", preset.name);
+                }
+                Some(Err(err)) => eprintln!("Load failed: {}", err),
+                None => eprintln!("Usage: load FILE"),
+            },
+            Some("trim") => {
+                let mut preset = Preset::new("current", params.clone());
+                let worst = preset.auto_trim(RENDER_SAMPLE_RATE as f32)?;
+                params = preset.params;
+                synth.set_params(params.clone())?;
+                println!("Peak {:.1} dB, {}", to_db(worst), describe(&params, &["trim"]));
+            }
+            Some("syx") => {
+                let path = words.next();
+                let number = words.next().map_or(Ok(1), str::parse::<usize>);
+                match (path, number) {
+                    (Some(path), Ok(number)) => match load_syx(path) {
+                        Ok(voices) => match number.checked_sub(1).and_then(|i| voices.get(i)) {
+                            Some(voice) => {
+                                let preset = voice.to_preset();
+                                params = preset.params;
+                                synth.switch_params(params.clone())?;
+                                println!("Imported '{}' (voice {} of {}, DX7 algorithm {} as {})",
+                                         preset.name, number, voices.len(), voice.algorithm,
+                                         describe(&params, &["algorithm"]));
+                            }
+                            None => eprintln!("No voice {} (found {})", number, voices.len()),
+                        },
+                        Err(err) => eprintln!("Import failed: {}", err),
+                    },
+                    _ => eprintln!("Usage: syx FILE [N]"),
+                }
+            }
+            Some("log") => match words.next() {
+                Some("off") => {
+                    synth.stop_log();
+                    println!("Logging stopped");
+                }
+                Some(path) => match EventLog::create(path, capture.sample_rate()) {
+                    Ok(log) => {
+                        synth.start_log(log);
+                        // Start from the current patch so the log replays alone
+                        synth.set_params(params.clone())?;
+                        println!("Logging to {}", path);
+                    }
+                    Err(err) => eprintln!("Log failed: {}", err),
+                },
+                None => eprintln!("Usage: log FILE|off"),
+            },
+            Some("zone") => {
+                let high = match words.next() {
+                    Some("low") => Some(false),
+                    Some("high") => Some(true),
+                    _ => None,
+                };
+                let source = words.next();
+                let split = words.next().map(str::parse::<u8>).transpose();
+                match (high, source, split) {
+                    (Some(high), Some("off"), _) => {
+                        let zone = if high { &mut params.high_zone } else { &mut params.low_zone };
+                        zone.enabled = false;
+                        synth.set_params(params.clone())?;
+                        println!("Key zone off");
+                    }
+                    (Some(high), Some(path), Ok(split)) => match Preset::load(path) {
+                        Ok(preset) => {
+                            let zone = if high { &mut params.high_zone } else { &mut params.low_zone };
+                            zone.enabled = true;
+                            zone.algorithm = preset.params.algorithm;
+                            zone.operators = preset.params.operators;
+                            if let Some(split) = split {
+                                zone.split = split.min(127) as f32;
+                            }
+                            let split = zone.split as u8;
+                            synth.set_params(params.clone())?;
+                            println!("Playing '{}' {} note {}", preset.name,
+                                     if high { "above" } else { "below" }, split);
+                        }
+                        Err(err) => eprintln!("Load failed: {}", err),
+                    },
+                    _ => eprintln!("Usage: zone low|high FILE [SPLIT] or zone low|high off"),
+                }
+            }
+            Some("quit") | None => break,
+            Some(other) => eprintln!("Unknown command '{}'", other),
         }
-        _ => {
-            println!("Invalid demo mode");
+    }
+    Ok(())
+}
+
+/// Listen for OSC messages on UDP `address` until Enter is pressed
+fn run_osc(
+    synth: &Controller,
+    address: (IpAddr, u16),
+    overrides: &[(&'static Parameter, f32)],
+) -> anyhow::Result<()> {
+    let mut params = FMParams::default();
+    apply_overrides(&mut params, overrides)?;
+    synth.set_params(params.clone())?;
+    
+    let socket = UdpSocket::bind(address)?;
+    println!("Listening for OSC on UDP {}:{}. Addresses:", address.0, address.1);
+    println!("  /note/on NOTE [VELOCITY]  velocity 0-1, or 0-127 as an int");
+    println!("  /note/off NOTE");
+    println!("  /notes/off                release every note");
+    println!("  /bend AMOUNT              -1 to 1");
+    println!("  /mod AMOUNT               mod wheel, 0 to 1");
+    println!("  /param/ID VALUE           set a parameter, e.g. /param/modulation_index 4;");
+    println!("                            a string value is parsed with units, e.g. \"-12dB\"");
+    println!("  /load FILE                load a JSON preset");
+    println!("  /tuning NAME [ROOT]       equal, just, meantone or pythagorean, on a root");
+    println!("                            such as \"A\" or \"F#\", or 0-11 from C");
+    println!("Press Enter to quit");
+    
+    let osc_synth = synth.clone();
+    std::thread::spawn(move || {
+        let mut buffer = [0; 65536];
+        while let Ok((len, _)) = socket.recv_from(&mut buffer) {
+            let Some(messages) = parse_osc(&buffer[..len]) else {
+                eprintln!("Ignoring malformed OSC packet");
+                continue;
+            };
+            for message in messages {
+                if let Err(err) = handle_osc(&message, &osc_synth, &mut params) {
+                    eprintln!("{}: {}", message.address, err);
+                }
+            }
+        }
+    });
+    
+    std::io::stdin().read_line(&mut String::new())?;
+    Ok(())
+}
+
+/// Act on one OSC message, keeping `params` as the patch playing
+fn handle_osc(message: &OscMessage, synth: &Controller, params: &mut FMParams) -> anyhow::Result<()> {
+    let amount = || {
+        message.args.first()
+            .and_then(OscArg::as_f32)
+            .filter(|amount| amount.is_finite())
+            .ok_or_else(|| anyhow::anyhow!("expected a finite amount"))
+    };
+    let note = || {
+        message.args.first()
+            .and_then(OscArg::as_f32)
+            .filter(|note| (0.0..=127.0).contains(note))
+            .map(|note| note.round() as u8)
+            .ok_or_else(|| anyhow::anyhow!("expected a note number 0-127"))
+    };
+    match message.address.as_str() {
+        "/note/on" => {
+            let velocity = match message.args.get(1) {
+                Some(&OscArg::Int(velocity)) => velocity as f32 / 127.0,
+                Some(arg) => arg.as_f32()
+                    .filter(|velocity| velocity.is_finite())
+                    .ok_or_else(|| anyhow::anyhow!("expected a velocity"))?,
+                None => 1.0,
+            };
+            // Velocity 0 releases the note, as in MIDI
+            if velocity > 0.0 {
+                synth.note_on(note()?, velocity.min(1.0));
+            } else {
+                synth.note_off(note()?);
+            }
+        }
+        "/note/off" => synth.note_off(note()?),
+        "/notes/off" => synth.all_notes_off(),
+        "/bend" => synth.pitch_bend(amount()?.clamp(-1.0, 1.0)),
+        "/mod" => synth.mod_wheel(amount()?.clamp(0.0, 1.0)),
+        "/load" => match message.args.first() {
+            Some(OscArg::String(path)) => {
+                let preset = Preset::load(path)?;
+                *params = preset.params;
+                synth.switch_params(params.clone())?;
+                println!("Loaded '{}'", preset.name);
+            }
+            _ => anyhow::bail!("expected a file name"),
+        },
+        "/tuning" => {
+            let temperament = match message.args.first() {
+                Some(OscArg::String(name)) => Temperament::from_name(name),
+                _ => None,
+            };
+            let temperament = temperament
+                .ok_or_else(|| anyhow::anyhow!("expected equal, just, meantone or pythagorean"))?;
+            let root = match message.args.get(1) {
+                Some(OscArg::String(name)) => parse_pitch_class(name),
+                Some(arg) => arg.as_f32()
+                    .filter(|root| (0.0..12.0).contains(root))
+                    .map(|root| root as u8),
+                None => Some(0),
+            };
+            let root = root.ok_or_else(|| anyhow::anyhow!("expected a root note such as A or F#"))?;
+            synth.set_tuning(KeyedTuning::new(temperament, root));
+            println!("Tuning: {} on {}", temperament.name(), PITCH_CLASSES[root as usize]);
+        }
+        address => {
+            let id = address.strip_prefix("/param/")
+                .ok_or_else(|| anyhow::anyhow!("unknown address"))?;
+            let param = Parameter::find(id)
+                .ok_or_else(|| anyhow::anyhow!("unknown parameter '{}'", id))?;
+            let value = match message.args.first() {
+                Some(OscArg::String(text)) => param.parse(text),
+                Some(arg) => arg.as_f32()
+                    .filter(|value| value.is_finite())
+                    .map(|value| param.clamp(value)),
+                None => None,
+            };
+            let value = value.ok_or_else(|| anyhow::anyhow!("expected a value for {}", param.name))?;
+            params.set(param.id, value)?;
+            synth.set_params(params.clone())?;
         }
     }
+    Ok(())
+}
+
+/// The example presets with `overrides` applied, after the default patch,
+/// for stepping through while playing live
+fn live_presets(
+    overrides: &[(&'static Parameter, f32)],
+) -> Result<Vec<(&'static str, FMParams)>, ParamError> {
+    std::iter::once(("Default", FMParams::default()))
+        .chain(example_presets())
+        .map(|(name, mut params)| apply_overrides(&mut params, overrides).map(|()| (name, params)))
+        .collect()
+}
+
+/// Play from the computer keyboard until Enter is pressed
+fn run_keys(
+    synth: &Controller,
+    sample_rate: f32,
+    overrides: &[(&'static Parameter, f32)],
+) -> anyhow::Result<()> {
+    let presets = live_presets(overrides)?;
+    let mut preset = 0;
+    synth.set_params(presets[preset].1.clone())?;
     
-    println!("\nDone!");
+    println!("Keys Z-comma and Q-P play two octaves, like a piano: S D, G H J, 2 3,");
+    println!("5 6 7 and 9 0 are the black keys. - and = shift the octave, [ and ] step through");
+    println!("the presets, space releases every note and Enter quits.");
+    
+    let _terminal = RawTerminal::enter()?;
+    let keys = read_keys();
+    let mut keyboard = Keyboard::new(sample_rate);
+    loop {
+        let key = keys.recv_timeout(Duration::from_millis(10));
+        keyboard.release_due(synth);
+        let key = match key {
+            Ok(Key::Char(key)) => key,
+            Ok(_) | Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if keyboard.press(synth, key) {
+            continue;
+        }
+        match key {
+            b'-' | b'=' => {
+                keyboard.shift_octave(key == b'=');
+                println!("Octave {}", keyboard.octave());
+            }
+            b'[' | b']' => {
+                preset = if key == b'[' {
+                    (preset + presets.len() - 1) % presets.len()
+                } else {
+                    (preset + 1) % presets.len()
+                };
+                let (name, params) = &presets[preset];
+                synth.switch_params(params.clone())?;
+                println!("Preset: {}", name);
+            }
+            b' ' => keyboard.release_all(synth),
+            b'\n' | b'\r' => break,
+            _ => {}
+        }
+    }
+    keyboard.release_all(synth);
     Ok(())
 }
 
+const PITCH_CLASSES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Parse a 1-based operator number into an index
+fn parse_operator(text: &str) -> Option<usize> {
+    let index = text.parse::<usize>().ok()?.checked_sub(1)?;
+    (index < NUM_OPERATORS).then_some(index)
+}
+
+/// Parse a root note name such as "A", "F#" or "Bb" into a pitch class
+fn parse_pitch_class(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let natural = PITCH_CLASSES.iter().position(|&pc| pc == letter.to_string())? as i32;
+    let accidental = match chars.as_str() {
+        "" => 0,
+        "#" => 1,
+        "b" => -1,
+        _ => return None,
+    };
+    Some((natural + accidental).rem_euclid(12) as u8)
+}
+
+/// Write the last `seconds` of output to a timestamped WAV file
+fn save_capture(capture: &CaptureBuffer, seconds: f32) -> anyhow::Result<()> {
+    let (samples, sample_rate) = (capture.last(seconds), capture.sample_rate());
+    
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = format!("capture-{}.wav", timestamp);
+    write_wav(&path, &samples, sample_rate as u32)?;
+    println!("Saved {:.1}s to {}", samples.len() as f32 / sample_rate, path);
+    Ok(())
+}
+
+/// Parse `id=value` arguments into parameter overrides
+fn parse_overrides(
+    args: impl Iterator<Item = String>,
+) -> anyhow::Result<Vec<(&'static Parameter, f32)>> {
+    args.map(|arg| {
+        let (id, text) = arg.split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected id=value, got '{}'", arg))?;
+        let param = Parameter::find(id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown parameter '{}'", id))?;
+        let value = param.parse(text)
+            .ok_or_else(|| anyhow::anyhow!("Invalid value '{}' for {}", text, param.name))?;
+        Ok((param, value))
+    })
+    .collect()
+}
+
+fn apply_overrides(
+    params: &mut FMParams,
+    overrides: &[(&'static Parameter, f32)],
+) -> Result<(), ParamError> {
+    for &(param, value) in overrides {
+        params.set(param.id, value)?;
+    }
+    Ok(())
+}
+
+/// Format the given parameters as "Name=value" pairs for display
+fn describe(params: &FMParams, ids: &[&str]) -> String {
+    ids.iter()
+        .filter_map(|&id| {
+            let param = Parameter::find(id)?;
+            Some(format!("{}={}", param.name, param.format(params.get(id)?)))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Build an operator stack from (ratio, level) pairs, carrier first;
+/// operators not listed are silent
+fn stack(ops: &[(f32, f32)]) -> [OperatorParams; NUM_OPERATORS] {
+    let mut operators = [OperatorParams::default(); NUM_OPERATORS];
+    for (op, &(ratio, level)) in operators.iter_mut().zip(ops) {
+        op.ratio = ratio;
+        op.level = level;
+    }
+    operators
+}
+
+/// Set an operator's envelope stage times (seconds) and sustain level
+fn set_envelope(op: &mut OperatorParams, attack: f32, decay: f32, sustain: f32, release: f32) {
+    op.attack = attack;
+    op.decay = decay;
+    op.sustain = sustain;
+    op.release = release;
+}
+
 // Example usage for creating different timbres:
 fn example_presets() -> Vec<(&'static str, FMParams)> {
     vec![
         ("Bell", FMParams {
-            carrier_freq: 440.0,
-            modulator_freq: 440.0,
+            frequency: 440.0,
+            operators: {
+                let mut ops = stack(&[(1.0, 1.0), (1.0, 1.0)]);
+                set_envelope(&mut ops[0], 0.001, 2.0, 0.0, 1.0);
+                set_envelope(&mut ops[1], 0.001, 1.2, 0.1, 1.0);
+                // Struck: a fast initial drop into a long ring
+                for op in &mut ops {
+                    op.decay_curve = EnvelopeCurve::Exponential;
+                    op.release_curve = EnvelopeCurve::Exponential;
+                }
+                ops
+            },
             modulation_index: 7.0,
             amplitude: 0.3,
+            // Bright strike that mellows as the bell rings out
+            index_attack: 0.001,
+            index_decay: 0.8,
+            index_sustain: 0.2,
+            index_env_amount: 0.8,
+            // Keep the top octaves from turning to noise
+            key_scale_index: 4.0,
+            ..FMParams::default()
         }),
         ("Bass", FMParams {
-            carrier_freq: 110.0,
-            modulator_freq: 110.0,
+            frequency: 110.0,
+            // Consistent punch on every note
+            operators: {
+                let mut ops = stack(&[(1.0, 1.0), (1.0, 1.0)]);
+                ops[1].phase = 90.0;
+                ops
+            },
+            phase_reset: true,
             modulation_index: 1.5,
             amplitude: 0.5,
+            ..FMParams::default()
         }),
         ("Electric Piano", FMParams {
-            carrier_freq: 440.0,
-            modulator_freq: 880.0,
+            frequency: 440.0,
+            // Harder playing brightens the tine; the carrier stays even
+            operators: {
+                let mut ops = stack(&[(1.0, 1.0), (2.0, 1.0)]);
+                ops[1].velocity_sens = 0.8;
+                // Slow body decay; the tine fades much faster
+                set_envelope(&mut ops[0], 0.002, 1.5, 0.3, 0.4);
+                set_envelope(&mut ops[1], 0.001, 0.4, 0.1, 0.3);
+                ops
+            },
             modulation_index: 3.0,
             amplitude: 0.4,
+            // A slightly detuned right channel widens the image
+            stereo_detune: 4.0,
+            // Tine bark on the attack, softer body while held
+            index_attack: 0.005,
+            index_decay: 0.3,
+            index_sustain: 0.3,
+            index_env_amount: 0.6,
+            ..FMParams::default()
         }),
         ("Brass", FMParams {
-            carrier_freq: 440.0,
-            modulator_freq: 440.0,
+            frequency: 440.0,
+            // Feedback on the modulator gives it a saw-like edge
+            operators: {
+                let mut ops = stack(&[(1.0, 1.0), (1.0, 1.0)]);
+                ops[1].feedback = 5.0;
+                // Modulator swells in behind the carrier for the brassy blat
+                set_envelope(&mut ops[1], 0.08, 0.2, 0.8, 0.3);
+                ops
+            },
             modulation_index: 2.5,
             amplitude: 0.4,
+            // Harder playing is louder and brighter
+            velocity_amp: 0.5,
+            velocity_index: 0.6,
+            ..FMParams::default()
+        }),
+        ("Metallic", FMParams {
+            frequency: 440.0,
+            // Full four-operator stack with inharmonic upper ratios
+            operators: stack(&[(1.0, 1.0), (3.5, 0.8), (1.41, 0.6), (7.0, 0.4)]),
+            modulation_index: 2.0,
+            amplitude: 0.3,
+            index_attack: 0.001,
+            index_decay: 1.2,
+            index_sustain: 0.3,
+            index_env_amount: 0.7,
+            ..FMParams::default()
+        }),
+        ("Organ", FMParams {
+            frequency: 440.0,
+            // Three drawbar-like carriers, the top one roughened by op 4
+            operators: stack(&[(0.5, 1.0), (1.0, 0.8), (2.0, 0.6), (4.0, 0.3)]),
+            algorithm: 7,
+            modulation_index: 1.0,
+            amplitude: 0.4,
+            // Gentle rotary-speaker tremolo
+            lfo_rate: 6.5,
+            lfo_depth: 0.25,
+            lfo_destination: LfoDestination::Amplitude,
+            ..FMParams::default()
+        }),
+        ("Clavinet", FMParams {
+            frequency: 440.0,
+            operators: {
+                let mut ops = stack(&[(1.0, 1.0), (3.0, 0.9)]);
+                set_envelope(&mut ops[0], 0.001, 0.8, 0.4, 0.05);
+                set_envelope(&mut ops[1], 0.001, 0.3, 0.3, 0.05);
+                ops
+            },
+            modulation_index: 2.5,
+            amplitude: 0.4,
+            velocity_index: 0.5,
+            // The string slapping back onto the pad at key-off
+            release_index: 2.0,
+            release_index_time: 0.02,
+            ..FMParams::default()
+        }),
+        ("Mono Lead", FMParams {
+            frequency: 440.0,
+            operators: {
+                let mut ops = stack(&[(1.0, 1.0), (1.0, 0.7)]);
+                ops[1].feedback = 3.0;
+                ops
+            },
+            modulation_index: 2.0,
+            amplitude: 0.4,
+            // One voice sliding between notes
+            polyphony: 1,
+            glide_time: 0.12,
+            lfo_rate: 5.5,
+            lfo_depth: 0.1,
+            ..FMParams::default()
         }),
     ]
 }
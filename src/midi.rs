@@ -0,0 +1,54 @@
+//! MIDI message decoding and pitch conversion
+
+/// A decoded MIDI channel message the synth responds to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiMessage {
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    /// 14-bit bend position, 8192 at centre
+    PitchBend { channel: u8, value: u16 },
+    ControlChange { channel: u8, controller: u8, value: u8 },
+}
+
+/// Controller number of the modulation wheel
+pub const CC_MOD_WHEEL: u8 = 1;
+
+impl MidiMessage {
+    /// Decode a raw MIDI message, returning `None` for anything unsupported.
+    /// A note-on with velocity 0 is treated as a note-off, as the spec requires.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (&status, data) = bytes.split_first()?;
+        let channel = status & 0x0F;
+
+        match (status & 0xF0, data) {
+            (0x80, &[note, velocity, ..]) => Some(MidiMessage::NoteOff { channel, note, velocity }),
+            (0x90, &[note, 0, ..]) => Some(MidiMessage::NoteOff { channel, note, velocity: 0 }),
+            (0x90, &[note, velocity, ..]) => Some(MidiMessage::NoteOn { channel, note, velocity }),
+            (0xB0, &[controller, value, ..]) => {
+                Some(MidiMessage::ControlChange { channel, controller, value })
+            }
+            (0xE0, &[lsb, msb, ..]) => {
+                let value = (msb as u16 & 0x7F) << 7 | (lsb as u16 & 0x7F);
+                Some(MidiMessage::PitchBend { channel, value })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Convert a 14-bit pitch bend value to -1.0 - 1.0. Centre is 8192; each
+/// side is scaled so both extremes reach full bend.
+pub fn bend_amount(value: u16) -> f32 {
+    let offset = value as f32 - 8192.0;
+    offset / if offset < 0.0 { 8192.0 } else { 8191.0 }
+}
+
+/// Convert a MIDI note number to frequency in Hz (A4 = note 69 = 440 Hz)
+pub fn note_to_freq(note: u8) -> f32 {
+    440.0 * 2.0_f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+/// Convert a frequency in Hz to a (fractional) MIDI note number
+pub fn freq_to_note(freq: f32) -> f32 {
+    69.0 + 12.0 * (freq / 440.0).log2()
+}
@@ -0,0 +1,174 @@
+//! Open Sound Control message decoding, for driving the synth from
+//! SuperCollider, TouchOSC, Max and the like over UDP
+
+/// An OSC argument of one of the common types; 64-bit numbers are
+/// narrowed to 32 bits
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Bool(bool),
+}
+
+impl OscArg {
+    /// The argument as a number, if it is one
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            OscArg::Int(value) => Some(value as f32),
+            OscArg::Float(value) => Some(value),
+            OscArg::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+            OscArg::String(_) => None,
+        }
+    }
+}
+
+/// A decoded OSC message
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscMessage {
+    /// Address pattern, e.g. `/note/on`
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// Decode a UDP packet holding an OSC message or bundle into its messages,
+/// returning `None` if it is malformed or uses an unsupported argument
+/// type. Bundle time tags are ignored, so bundled messages apply at once.
+pub fn parse_osc(bytes: &[u8]) -> Option<Vec<OscMessage>> {
+    let mut messages = Vec::new();
+    parse_packet(bytes, &mut messages)?;
+    Some(messages)
+}
+
+fn parse_packet(bytes: &[u8], messages: &mut Vec<OscMessage>) -> Option<()> {
+    let Some(mut rest) = bytes.strip_prefix(b"#bundle\0") else {
+        messages.push(parse_message(bytes)?);
+        return Some(());
+    };
+    rest = rest.get(8..)?;  // Time tag
+    while !rest.is_empty() {
+        let size = usize::try_from(read_i32(&mut rest)?).ok()?;
+        let (element, after) = rest.split_at_checked(size)?;
+        parse_packet(element, messages)?;
+        rest = after;
+    }
+    Some(())
+}
+
+fn parse_message(mut bytes: &[u8]) -> Option<OscMessage> {
+    let address = read_string(&mut bytes)?;
+    if !address.starts_with('/') {
+        return None;
+    }
+    // Very old senders omit the type tags entirely
+    if bytes.is_empty() {
+        return Some(OscMessage { address, args: Vec::new() });
+    }
+    let tags = read_string(&mut bytes)?;
+    let tags = tags.strip_prefix(',')?;
+    let mut args = Vec::with_capacity(tags.len());
+    for tag in tags.chars() {
+        let arg = match tag {
+            'i' => OscArg::Int(read_i32(&mut bytes)?),
+            'f' => OscArg::Float(f32::from_bits(read_i32(&mut bytes)? as u32)),
+            'h' => OscArg::Int(i64::from_be_bytes(read_bytes(&mut bytes)?) as i32),
+            'd' => OscArg::Float(f64::from_be_bytes(read_bytes(&mut bytes)?) as f32),
+            's' | 'S' => OscArg::String(read_string(&mut bytes)?),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            _ => return None,
+        };
+        args.push(arg);
+    }
+    Some(OscMessage { address, args })
+}
+
+fn read_bytes<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let (value, rest) = bytes.split_first_chunk::<N>()?;
+    *bytes = rest;
+    Some(*value)
+}
+
+fn read_i32(bytes: &mut &[u8]) -> Option<i32> {
+    read_bytes(bytes).map(i32::from_be_bytes)
+}
+
+/// Read a null-terminated string padded to a multiple of four bytes
+fn read_string(bytes: &mut &[u8]) -> Option<String> {
+    let len = bytes.iter().position(|&b| b == 0)?;
+    let text = std::str::from_utf8(&bytes[..len]).ok()?.to_string();
+    *bytes = bytes.get((len + 4) & !3..)?;
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE_ON: &[u8] = b"/note/on\0\0\0\0,if\0\0\0\0\x45\x3f\x4c\xcc\xcd";
+
+    #[test]
+    fn message() {
+        let messages = parse_osc(NOTE_ON).unwrap();
+        assert_eq!(messages, [OscMessage {
+            address: "/note/on".to_string(),
+            args: vec![OscArg::Int(69), OscArg::Float(0.8)],
+        }]);
+    }
+
+    #[test]
+    fn strings_are_padded_to_four_bytes() {
+        // "/a" pads to 4 bytes; "/abc" needs a whole extra word for its null
+        let messages = parse_osc(b"/a\0\0,sT\0hi\0\0").unwrap();
+        assert_eq!(messages[0].address, "/a");
+        assert_eq!(messages[0].args, [OscArg::String("hi".to_string()), OscArg::Bool(true)]);
+        let messages = parse_osc(b"/abc\0\0\0\0,F\0\0").unwrap();
+        assert_eq!(messages[0].address, "/abc");
+        assert_eq!(messages[0].args, [OscArg::Bool(false)]);
+        // Padding cut short
+        assert_eq!(parse_osc(b"/abc\0\0\0"), None);
+    }
+
+    #[test]
+    fn message_without_type_tags() {
+        let messages = parse_osc(b"/notes/off\0\0").unwrap();
+        assert_eq!(messages[0].args, []);
+    }
+
+    #[test]
+    fn bundle() {
+        let mut packet = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for _ in 0..2 {
+            packet.extend((NOTE_ON.len() as i32).to_be_bytes());
+            packet.extend(NOTE_ON);
+        }
+        let messages = parse_osc(&packet).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].address, "/note/on");
+    }
+
+    #[test]
+    fn bad_bundle_sizes() {
+        let bundle = |size: i32| {
+            let mut packet = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+            packet.extend(size.to_be_bytes());
+            packet.extend(NOTE_ON);
+            parse_osc(&packet)
+        };
+        assert!(bundle(NOTE_ON.len() as i32).is_some());
+        assert_eq!(bundle(-4), None);
+        assert_eq!(bundle(NOTE_ON.len() as i32 + 4), None);
+        assert_eq!(bundle(i32::MAX), None);
+    }
+
+    #[test]
+    fn unsupported_type_tag() {
+        // A blob, which the synth has no use for
+        assert_eq!(parse_osc(b"/load\0\0\0,b\0\0\0\0\0\x01\x07\0\0\0"), None);
+    }
+
+    #[test]
+    fn address_must_start_with_a_slash() {
+        assert_eq!(parse_osc(b"note\0\0\0\0,\0\0\0"), None);
+    }
+}
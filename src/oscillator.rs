@@ -0,0 +1,429 @@
+//! Four-operator FM oscillator
+
+use std::f32::consts::{PI, TAU};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ALGORITHMS, Algorithm, Envelope, FMParams, NUM_OPERATORS};
+
+/// Entries in one cycle of the sine table; a power of two
+const SINE_TABLE_LEN: usize = 4096;
+
+/// Samples [`FMOscillator::process_block`] renders per pass
+const BLOCK_LEN: usize = 64;
+
+/// Furthest in octaves exponential FM may push an operator's pitch either
+/// way, keeping frequencies finite at extreme depths
+const MAX_EXP_OCTAVES: f32 = 8.0;
+
+/// One cycle of sine plus a wrapped entry for interpolation, shared by
+/// every oscillator
+static SINE_TABLE: OnceLock<[f32; SINE_TABLE_LEN + 1]> = OnceLock::new();
+
+/// How an operator takes the modulation routed into it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ModulationInput {
+    /// Linear phase modulation, as on DX synths: the modulator shifts the
+    /// phase, leaving the average pitch where it is
+    #[default]
+    Phase,
+    /// Exponential FM, as on modular synths: the modulator bends the pitch
+    /// by one octave per unit of modulation index, so deep modulation
+    /// also raises the average pitch
+    Exponential,
+}
+
+impl ModulationInput {
+    pub const ALL: [ModulationInput; 2] = [ModulationInput::Phase, ModulationInput::Exponential];
+
+    /// Names in [`ALL`](Self::ALL) order, as shown by the parameter table
+    pub const NAMES: &'static [&'static str] = &["phase", "exp"];
+
+    /// Look up by position in [`ALL`](Self::ALL)
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// FM Synthesizer oscillator
+#[derive(Clone)]
+pub struct FMOscillator {
+    sample_rate: f32,
+    phases: [f32; NUM_OPERATORS],  // Per-operator phase in cycles (0.0 - 1.0)
+    current: Ramped,       // Ramped values in use, gliding towards target
+    target: Ramped,
+    step: Ramped,          // Change per sample while ramping
+    ramp_left: usize,      // Samples until current reaches target
+    params: FMParams,
+    algorithm: Algorithm,  // Routing selected by params.algorithm
+    velocity: f32,         // Velocity of the current note (0.0 - 1.0)
+    feedback: [[f32; 2]; NUM_OPERATORS],  // Last two outputs of each operator
+    envelopes: [Envelope; NUM_OPERATORS],  // Per-operator level envelopes
+    sine_table: &'static [f32; SINE_TABLE_LEN + 1],
+}
+
+impl FMOscillator {
+    /// Create an oscillator running at `sample_rate` Hz
+    pub fn new(sample_rate: f32, params: FMParams) -> Self {
+        let mut oscillator = Self {
+            sample_rate,
+            phases: [0.0; NUM_OPERATORS],
+            current: Ramped::new(&params),
+            target: Ramped::new(&params),
+            step: Ramped::new(&params),
+            ramp_left: 0,
+            algorithm: lookup_algorithm(params.algorithm),
+            velocity: 1.0,
+            feedback: [[0.0; 2]; NUM_OPERATORS],
+            envelopes: std::array::from_fn(|_| Envelope::new(sample_rate)),
+            // Built here rather than by the first sample, on the audio thread
+            sine_table: SINE_TABLE.get_or_init(|| {
+                std::array::from_fn(|i| (TAU * i as f32 / SINE_TABLE_LEN as f32).sin())
+            }),
+            params: params.clone(),
+        };
+        oscillator.set_params(params);
+        oscillator
+    }
+
+    /// Generate next sample using FM synthesis, with the modulation index
+    /// scaled by `index_scale` and every operator frequency by
+    /// `pitch_scale` (1.0 leaves either unchanged)
+    pub fn next_sample(&mut self, index_scale: f32, pitch_scale: f32) -> f32 {
+        let depth = self.current.modulation_index * index_scale;
+        
+        // Modulators always have higher numbers than the operators they
+        // drive, so running from op 4 down to op 1 computes every
+        // modulator before it is needed
+        let mut outputs = [0.0; NUM_OPERATORS];
+        let mut bends = [1.0; NUM_OPERATORS];  // Pitch factors from exponential FM
+        let mut mix = 0.0;
+        for i in (0..NUM_OPERATORS).rev() {
+            let modulation: f32 = (i + 1..NUM_OPERATORS)
+                .filter(|&j| self.algorithm.modulates(j, i))
+                .map(|j| outputs[j])
+                .sum();
+            
+            // Self-feedback from the average of the last two outputs, which
+            // keeps high feedback from breaking into noise
+            let op = &self.params.operators[i];
+            let history = &mut self.feedback[i];
+            let self_mod = feedback_depth(self.current.feedback[i]) * (history[0] + history[1]) * 0.5;
+            
+            let mut angle = 2.0 * PI * self.phases[i];
+            match op.modulation_input {
+                ModulationInput::Phase => angle += modulation * depth,
+                ModulationInput::Exponential => bends[i] = exp_bend(modulation * depth),
+            }
+            angle += self_mod;
+            let out = if self.params.precise_sine { angle.sin() } else { table_sine(self.sine_table, angle) };
+            history[1] = history[0];
+            history[0] = out;
+            
+            // On a modulator the envelope shapes brightness, on a carrier
+            // loudness
+            let envelope = self.envelopes[i].process();
+            let level = self.current.levels[i] * (1.0 - op.velocity_sens * (1.0 - self.velocity))
+                * envelope;
+            outputs[i] = out * level;
+            if self.algorithm.is_carrier(i) {
+                mix += outputs[i];
+            }
+        }
+        
+        // Average the carriers so parallel algorithms don't clip
+        let carrier = mix / self.algorithm.carrier_count() as f32;
+        
+        // Update phases, wrapping to prevent overflow
+        for ((phase, ratio), bend) in self.phases.iter_mut().zip(&self.current.ratios).zip(bends) {
+            *phase += self.params.frequency * pitch_scale * ratio / self.sample_rate * bend;
+            *phase -= phase.floor();
+        }
+        
+        // Return amplitude-scaled output
+        let out = carrier * self.current.amplitude;
+        self.advance_ramp();
+        out
+    }
+
+    /// Render a block into `output`, overwriting it, with per-sample index
+    /// and pitch scales as for [`next_sample`](Self::next_sample). Gives
+    /// the same samples, but works through the block one operator at a
+    /// time so the compiler can vectorize the inner loops; used while no
+    /// parameter ramp is running and the sine isn't precise.
+    pub fn process_block(&mut self, index_scale: &[f32], pitch_scale: &[f32], output: &mut [f32]) {
+        if self.ramp_left > 0 || self.params.precise_sine {
+            for ((out, &index), &pitch) in output.iter_mut().zip(index_scale).zip(pitch_scale) {
+                *out = self.next_sample(index, pitch);
+            }
+            return;
+        }
+        
+        let chunks = output.chunks_mut(BLOCK_LEN)
+            .zip(index_scale.chunks(BLOCK_LEN))
+            .zip(pitch_scale.chunks(BLOCK_LEN));
+        for ((output, index_scale), pitch_scale) in chunks {
+            self.render_chunk(index_scale, pitch_scale, output);
+        }
+    }
+
+    /// Start a note at `velocity` (0.0 - 1.0), triggering every operator
+    /// envelope. Operators restart at their start phases if phase reset is
+    /// enabled, otherwise they free-run.
+    pub fn retrigger(&mut self, velocity: f32) {
+        self.velocity = velocity.clamp(0.0, 1.0);
+        for envelope in &mut self.envelopes {
+            envelope.trigger();
+        }
+        if self.params.phase_reset {
+            for (phase, op) in self.phases.iter_mut().zip(&self.params.operators) {
+                *phase = (op.phase / 360.0).rem_euclid(1.0);
+            }
+            self.feedback = [[0.0; 2]; NUM_OPERATORS];
+        }
+    }
+
+    /// Match `other`'s operator phases, leading them by `offset` cycles
+    pub fn sync_phases(&mut self, other: &FMOscillator, offset: f32) {
+        for (phase, &other) in self.phases.iter_mut().zip(&other.phases) {
+            *phase = (other + offset).rem_euclid(1.0);
+        }
+    }
+
+    /// Move every operator envelope to its release stage
+    pub fn release(&mut self) {
+        for envelope in &mut self.envelopes {
+            envelope.release();
+        }
+    }
+
+    /// Silence every operator immediately and clear feedback history
+    pub fn reset(&mut self) {
+        for envelope in &mut self.envelopes {
+            envelope.reset();
+        }
+        self.feedback = [[0.0; 2]; NUM_OPERATORS];
+    }
+
+    /// True while any carrier envelope is still sounding
+    pub fn is_active(&self) -> bool {
+        (0..NUM_OPERATORS)
+            .any(|i| self.algorithm.is_carrier(i) && self.envelopes[i].is_active())
+    }
+
+    /// Loudest carrier's current envelope level times its operator level,
+    /// a rough measure of how loud the oscillator is sounding
+    pub fn level(&self) -> f32 {
+        (0..NUM_OPERATORS)
+            .filter(|&i| self.algorithm.is_carrier(i))
+            .map(|i| self.envelopes[i].level() * self.current.levels[i])
+            .fold(0.0, f32::max)
+    }
+
+    pub fn params(&self) -> &FMParams {
+        &self.params
+    }
+
+    /// Replace the parameters without resetting phase or envelopes
+    pub fn set_params(&mut self, params: FMParams) {
+        self.ramp_params(params, 0);
+    }
+
+    /// Replace the parameters like [`set_params`](Self::set_params), but
+    /// move modulation index, amplitude and operator ratios, levels and
+    /// feedback linearly to their new values over `samples` samples, so
+    /// automation doesn't step at block boundaries
+    pub fn ramp_params(&mut self, params: FMParams, samples: usize) {
+        for (envelope, op) in self.envelopes.iter_mut().zip(&params.operators) {
+            envelope.set_adsr(op.attack, op.decay, op.sustain, op.release);
+            envelope.set_curves(op.attack_curve, op.decay_curve, op.release_curve);
+            envelope.set_loop(op.envelope_loop);
+            if op.rate_level {
+                envelope.set_segments(op.segment_times, op.segment_levels);
+            }
+        }
+        self.algorithm = lookup_algorithm(params.algorithm);
+        self.target = Ramped::new(&params);
+        self.ramp_left = samples;
+        if samples == 0 {
+            self.current = self.target;
+        } else {
+            let samples = samples as f32;
+            self.step = self.target.zip(self.current, |target, current| (target - current) / samples);
+        }
+        self.params = params;
+    }
+
+    /// One chunk of [`process_block`](Self::process_block), up to
+    /// `BLOCK_LEN` samples
+    fn render_chunk(&mut self, index_scale: &[f32], pitch_scale: &[f32], output: &mut [f32]) {
+        let len = output.len();
+        let mut outputs = [[0.0; BLOCK_LEN]; NUM_OPERATORS];
+        let mut mix = [0.0; BLOCK_LEN];
+        for i in (0..NUM_OPERATORS).rev() {
+            let mut modulation = [0.0; BLOCK_LEN];
+            for j in (i + 1..NUM_OPERATORS).filter(|&j| self.algorithm.modulates(j, i)) {
+                for (sum, &input) in modulation[..len].iter_mut().zip(&outputs[j][..len]) {
+                    *sum += input;
+                }
+            }
+            
+            // Phases accumulate sample by sample; everything after is per
+            // sample independent unless the operator feeds back
+            let op = &self.params.operators[i];
+            let mut angle = [0.0; BLOCK_LEN];
+            let ratio = self.current.ratios[i];
+            let phase = &mut self.phases[i];
+            let depth = self.current.modulation_index;
+            match op.modulation_input {
+                ModulationInput::Phase => {
+                    for (angle, &pitch) in angle[..len].iter_mut().zip(pitch_scale) {
+                        *angle = 2.0 * PI * *phase;
+                        *phase += self.params.frequency * pitch * ratio / self.sample_rate;
+                        if *phase >= 1.0 {
+                            *phase -= phase.floor();
+                        }
+                    }
+                    let angles = angle[..len].iter_mut().zip(&modulation).zip(index_scale);
+                    for ((angle, &modulation), &index) in angles {
+                        *angle += modulation * (depth * index);
+                    }
+                }
+                ModulationInput::Exponential => {
+                    let inputs = angle[..len].iter_mut().zip(pitch_scale).zip(&modulation).zip(index_scale);
+                    for (((angle, &pitch), &modulation), &index) in inputs {
+                        *angle = 2.0 * PI * *phase;
+                        let bend = exp_bend(modulation * (depth * index));
+                        *phase += self.params.frequency * pitch * ratio / self.sample_rate * bend;
+                        if *phase >= 1.0 {
+                            *phase -= phase.floor();
+                        }
+                    }
+                }
+            }
+            
+            let level = self.current.levels[i] * (1.0 - op.velocity_sens * (1.0 - self.velocity));
+            let mut envelope = [0.0; BLOCK_LEN];
+            self.envelopes[i].process_block(&mut envelope[..len]);
+            
+            let table = self.sine_table;
+            let out = &mut outputs[i];
+            if self.current.feedback[i] > 0.0 {
+                let feedback = feedback_depth(self.current.feedback[i]);
+                let history = &mut self.feedback[i];
+                for n in 0..len {
+                    let self_mod = feedback * (history[0] + history[1]) * 0.5;
+                    let sine = table_sine(table, angle[n] + self_mod);
+                    history[1] = history[0];
+                    history[0] = sine;
+                    out[n] = sine * (level * envelope[n]);
+                }
+            } else {
+                for ((out, &angle), &envelope) in out[..len].iter_mut().zip(&angle).zip(&envelope) {
+                    *out = table_sine(table, angle) * (level * envelope);
+                }
+                // Keep the history a later feedback change would start from
+                let sine = |n: usize| table_sine(table, angle[n]);
+                self.feedback[i] = match len {
+                    1 => [sine(0), self.feedback[i][0]],
+                    _ => [sine(len - 1), sine(len - 2)],
+                };
+            }
+            if self.algorithm.is_carrier(i) {
+                for (mix, &out) in mix[..len].iter_mut().zip(&out[..len]) {
+                    *mix += out;
+                }
+            }
+        }
+        
+        let carriers = self.algorithm.carrier_count() as f32;
+        for (output, &mix) in output.iter_mut().zip(&mix) {
+            *output = mix / carriers * self.current.amplitude;
+        }
+    }
+
+    fn advance_ramp(&mut self) {
+        match self.ramp_left {
+            0 => {}
+            1 => {
+                self.current = self.target;
+                self.ramp_left = 0;
+            }
+            _ => {
+                self.current = self.current.zip(self.step, |current, step| current + step);
+                self.ramp_left -= 1;
+            }
+        }
+    }
+}
+
+/// Patch values that ramp across a block rather than stepping
+#[derive(Clone, Copy)]
+struct Ramped {
+    modulation_index: f32,
+    amplitude: f32,                // Including trim
+    ratios: [f32; NUM_OPERATORS],  // After ratio snap
+    levels: [f32; NUM_OPERATORS],
+    feedback: [f32; NUM_OPERATORS],
+}
+
+impl Ramped {
+    fn new(params: &FMParams) -> Self {
+        Self {
+            modulation_index: params.modulation_index,
+            amplitude: params.amplitude * params.trim,
+            ratios: std::array::from_fn(|i| params.snapped_ratio(i)),
+            levels: std::array::from_fn(|i| params.operators[i].level),
+            feedback: std::array::from_fn(|i| params.operators[i].feedback),
+        }
+    }
+
+    /// Combine with `other` value by value
+    fn zip(self, other: Self, f: impl Fn(f32, f32) -> f32) -> Self {
+        Self {
+            modulation_index: f(self.modulation_index, other.modulation_index),
+            amplitude: f(self.amplitude, other.amplitude),
+            ratios: std::array::from_fn(|i| f(self.ratios[i], other.ratios[i])),
+            levels: std::array::from_fn(|i| f(self.levels[i], other.levels[i])),
+            feedback: std::array::from_fn(|i| f(self.feedback[i], other.feedback[i])),
+        }
+    }
+}
+
+/// Sine of `angle` radians from `table`, linearly interpolated; within
+/// about 1e-6 of the exact value
+fn table_sine(table: &[f32; SINE_TABLE_LEN + 1], angle: f32) -> f32 {
+    let position = angle * (SINE_TABLE_LEN as f32 / TAU);
+    // Floor by truncating, which unlike f32::floor needs no library call
+    let truncated = position as i32;
+    let whole = if truncated as f32 > position { truncated - 1 } else { truncated };
+    let index = (whole & (SINE_TABLE_LEN as i32 - 1)) as usize;
+    let (a, b) = (table[index], table[index + 1]);
+    a + (b - a) * (position - whole as f32)
+}
+
+/// Pitch factor for exponential FM of `octaves`, limited to
+/// [`MAX_EXP_OCTAVES`] either way
+fn exp_bend(octaves: f32) -> f32 {
+    octaves.clamp(-MAX_EXP_OCTAVES, MAX_EXP_OCTAVES).exp2()
+}
+
+/// Phase deviation in radians for a DX-style feedback amount: off at 0,
+/// doubling per step up to pi at 7
+fn feedback_depth(amount: f32) -> f32 {
+    if amount <= 0.0 {
+        0.0
+    } else {
+        PI * 2.0_f32.powf(amount - 7.0)
+    }
+}
+
+/// Resolve an algorithm number, falling back to the serial stack
+fn lookup_algorithm(number: usize) -> Algorithm {
+    Algorithm::get(number).copied().unwrap_or(ALGORITHMS[0])
+}
@@ -0,0 +1,171 @@
+//! Keeping the audio callback on time when rendering falls behind
+
+use std::time::Instant;
+
+use crate::FMSynth;
+
+/// Seconds over which [`OverrunPolicy::Fade`] ramps down to silence
+const FADE_SECONDS: f32 = 0.005;
+
+/// What a [`BlockRenderer`] does with blocks it can no longer render in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverrunPolicy {
+    /// Silence the oldest voice before each late block, then render it
+    DropVoices,
+    /// Loop the last block's worth of audio rendered on time in place of
+    /// late blocks
+    Repeat,
+    /// Fade out from the last sample played, then play silence
+    Fade,
+}
+
+impl OverrunPolicy {
+    pub const ALL: [OverrunPolicy; 3] = [
+        OverrunPolicy::DropVoices,
+        OverrunPolicy::Repeat,
+        OverrunPolicy::Fade,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OverrunPolicy::DropVoices => "drop",
+            OverrunPolicy::Repeat => "repeat",
+            OverrunPolicy::Fade => "fade",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Renders an audio callback's buffer block by block, following an
+/// [`OverrunPolicy`] for any block started after the callback's deadline
+pub struct BlockRenderer {
+    policy: OverrunPolicy,
+    history_left: Vec<f32>,  // Ring of the latest samples rendered, for Repeat
+    history_right: Vec<f32>,
+    written: usize,          // Where the next sample rendered goes
+    filled: usize,           // Samples of history recorded so far
+    replay: usize,           // Samples repeated since the last one rendered
+    hold: [f32; 2],       // Last frame rendered, where a fade starts
+    fade: f32,            // Fade gain, 1.0 until a fade starts
+    fade_step: f32,       // Gain lost per sample while fading
+    overruns: u64,
+}
+
+impl BlockRenderer {
+    /// Create a renderer for blocks of up to `block_frames` frames. This
+    /// allocates, so create it before the audio stream starts.
+    pub fn new(sample_rate: f32, block_frames: usize, policy: OverrunPolicy) -> Self {
+        Self {
+            policy,
+            history_left: vec![0.0; block_frames],
+            history_right: vec![0.0; block_frames],
+            written: 0,
+            filled: 0,
+            replay: 0,
+            hold: [0.0; 2],
+            fade: 1.0,
+            fade_step: 1.0 / (FADE_SECONDS * sample_rate),
+            overruns: 0,
+        }
+    }
+
+    /// Render the next stereo block from `synth`, overwriting `left` and
+    /// `right`, or fill it according to the policy if `deadline` has passed
+    pub fn render(
+        &mut self,
+        synth: &mut FMSynth,
+        left: &mut [f32],
+        right: &mut [f32],
+        deadline: Instant,
+    ) {
+        if Instant::now() < deadline {
+            synth.process_stereo(left, right);
+            self.remember(left, right);
+            return;
+        }
+
+        self.overruns += 1;
+        match self.policy {
+            OverrunPolicy::DropVoices => {
+                synth.drop_voice();
+                synth.process_stereo(left, right);
+                self.remember(left, right);
+            }
+            OverrunPolicy::Repeat => {
+                // Carry on through the loop from where the last late block
+                // left off, oldest sample first
+                let len = self.history_left.len();
+                for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                    (*l, *r) = match self.filled {
+                        0 => (0.0, 0.0),
+                        filled => {
+                            let i = (self.written + len - filled + self.replay % filled) % len;
+                            (self.history_left[i], self.history_right[i])
+                        }
+                    };
+                    self.replay += 1;
+                }
+            }
+            OverrunPolicy::Fade => {
+                for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                    self.fade = (self.fade - self.fade_step).max(0.0);
+                    (*l, *r) = (self.hold[0] * self.fade, self.hold[1] * self.fade);
+                }
+            }
+        }
+    }
+
+    /// Blocks that missed their deadline so far
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    /// Add samples rendered by the synth to the history for Repeat and
+    /// Fade. Callers may render a callback's buffer in pieces, so this
+    /// appends rather than keeping only the latest piece.
+    fn remember(&mut self, left: &[f32], right: &[f32]) {
+        let len = self.history_left.len();
+        if len > 0 {
+            for (&l, &r) in left.iter().zip(right) {
+                (self.history_left[self.written], self.history_right[self.written]) = (l, r);
+                self.written = (self.written + 1) % len;
+            }
+            self.filled = (self.filled + left.len()).min(len);
+        }
+        self.replay = 0;
+        if let (Some(&l), Some(&r)) = (left.last(), right.last()) {
+            self.hold = [l, r];
+        }
+        self.fade = 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FMParams;
+
+    #[test]
+    fn repeat_loops_the_whole_block_from_its_pieces() {
+        let mut synth = FMSynth::new(48000.0, FMParams::default()).unwrap();
+        synth.note_on(69, 1.0);
+        let mut renderer = BlockRenderer::new(48000.0, 64, OverrunPolicy::Repeat);
+        let on_time = Instant::now() + std::time::Duration::from_secs(60);
+        let (mut left, mut right) = ([0.0; 64], [0.0; 64]);
+        // Rendered in pieces, as when a scheduled command splits the block
+        for (l, r) in left.chunks_mut(16).zip(right.chunks_mut(16)) {
+            renderer.render(&mut synth, l, r, on_time);
+        }
+
+        let (mut late_left, mut late_right) = ([0.0; 64], [0.0; 64]);
+        for (l, r) in late_left.chunks_mut(16).zip(late_right.chunks_mut(16)) {
+            renderer.render(&mut synth, l, r, Instant::now());
+        }
+        assert_eq!(late_left, left);
+        assert_eq!(late_right, right);
+        assert_eq!(renderer.overruns(), 4);
+    }
+}
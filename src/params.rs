@@ -0,0 +1,931 @@
+//! Synth parameters and the metadata frontends use to edit them
+
+use std::f32::consts::{E, PI, SQRT_2};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{EnvelopeCurve, EnvelopeLoop, LfoDestination, ModulationInput};
+
+/// Number of operators in the FM engine
+pub const NUM_OPERATORS: usize = 4;
+
+/// Ratios that [`FMParams::ratio_snap`] pulls operator ratios towards:
+/// harmonics, half-harmonics and the classic inharmonic bell and metal
+/// ratios
+pub const SNAP_RATIOS: [f32; 21] = [
+    0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0, 5.0, 6.0, 7.0, 8.0, 10.0, 12.0, 16.0,
+    SQRT_2, 1.618_034, E, PI, 2.0 * SQRT_2, 2.0 * PI,
+];
+
+/// Settings for one FM operator
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OperatorParams {
+    /// Frequency as a multiple of the note frequency
+    pub ratio: f32,
+    /// Output level (0.0 - 1.0); on a modulator this scales modulation depth
+    pub level: f32,
+    /// Start phase in degrees, applied at note-on when phase reset is enabled
+    pub phase: f32,
+    /// How much note velocity scales this operator's level (0.0 - 1.0).
+    /// At 0 the level is fixed; at 1 it follows velocity fully.
+    pub velocity_sens: f32,
+    /// Self-feedback amount (0.0 - 7.0, DX style): the operator's output
+    /// phase-modulates itself, turning a sine towards a sawtooth. Each step
+    /// doubles the depth.
+    pub feedback: f32,
+    /// Envelope attack time in seconds
+    pub attack: f32,
+    /// Envelope decay time in seconds
+    pub decay: f32,
+    /// Envelope sustain level (0.0 - 1.0)
+    pub sustain: f32,
+    /// Envelope release time in seconds
+    pub release: f32,
+    /// Shape of the envelope's attack stage
+    pub attack_curve: EnvelopeCurve,
+    /// Shape of the envelope's decay stage
+    pub decay_curve: EnvelopeCurve,
+    /// Shape of the envelope's release stage
+    pub release_curve: EnvelopeCurve,
+    /// Use the four-segment rate/level envelope below instead of ADSR
+    pub rate_level: bool,
+    /// Rate/level envelope segment times in seconds; the fourth is the
+    /// release
+    pub segment_times: [f32; 4],
+    /// Rate/level envelope levels (0.0 - 1.0) reached by each segment. A
+    /// note starts from the fourth and holds the third.
+    pub segment_levels: [f32; 4],
+    /// Segments of the rate/level envelope that repeat while the key is
+    /// held
+    pub envelope_loop: EnvelopeLoop,
+    /// Whether modulation routed into this operator shifts its phase or
+    /// bends its pitch exponentially
+    pub modulation_input: ModulationInput,
+}
+
+impl Default for OperatorParams {
+    fn default() -> Self {
+        Self {
+            ratio: 1.0,
+            level: 0.0,
+            phase: 0.0,
+            velocity_sens: 0.0,
+            feedback: 0.0,
+            attack: 0.01,
+            decay: 0.1,
+            sustain: 0.7,
+            release: 0.5,
+            attack_curve: EnvelopeCurve::Linear,
+            decay_curve: EnvelopeCurve::Linear,
+            release_curve: EnvelopeCurve::Linear,
+            rate_level: false,
+            segment_times: [0.01, 0.1, 0.1, 0.5],
+            segment_levels: [1.0, 0.7, 0.7, 0.0],
+            envelope_loop: EnvelopeLoop::Off,
+            modulation_input: ModulationInput::Phase,
+        }
+    }
+}
+
+impl OperatorParams {
+    /// Read a field by the part of its id after `opN_`
+    fn get(&self, field: &str) -> Option<f32> {
+        match field {
+            "ratio" => Some(self.ratio),
+            "level" => Some(self.level),
+            "phase" => Some(self.phase),
+            "velocity" => Some(self.velocity_sens),
+            "feedback" => Some(self.feedback),
+            "attack" => Some(self.attack),
+            "decay" => Some(self.decay),
+            "sustain" => Some(self.sustain),
+            "release" => Some(self.release),
+            "attack_curve" => Some(self.attack_curve.index() as f32),
+            "decay_curve" => Some(self.decay_curve.index() as f32),
+            "release_curve" => Some(self.release_curve.index() as f32),
+            "rate_level" => Some(if self.rate_level { 1.0 } else { 0.0 }),
+            "loop" => Some(self.envelope_loop.index() as f32),
+            "input" => Some(self.modulation_input.index() as f32),
+            _ => match segment_field(field)? {
+                ("time", segment) => Some(self.segment_times[segment]),
+                (_, segment) => Some(self.segment_levels[segment]),
+            },
+        }
+    }
+
+    /// Write a field by the part of its id after `opN_`, returning `None`
+    /// if there is no such field. The value has already been validated.
+    fn set(&mut self, field: &str, value: f32) -> Option<()> {
+        match field {
+            "ratio" => self.ratio = value,
+            "level" => self.level = value,
+            "phase" => self.phase = value,
+            "velocity" => self.velocity_sens = value,
+            "feedback" => self.feedback = value,
+            "attack" => self.attack = value,
+            "decay" => self.decay = value,
+            "sustain" => self.sustain = value,
+            "release" => self.release = value,
+            "attack_curve" | "decay_curve" | "release_curve" => {
+                let curve = EnvelopeCurve::from_index(value.round() as usize)
+                    .unwrap_or_default();
+                match field {
+                    "attack_curve" => self.attack_curve = curve,
+                    "decay_curve" => self.decay_curve = curve,
+                    _ => self.release_curve = curve,
+                }
+            }
+            "rate_level" => self.rate_level = value >= 0.5,
+            "loop" => {
+                self.envelope_loop = EnvelopeLoop::from_index(value.round() as usize)
+                    .unwrap_or_default();
+            }
+            "input" => {
+                self.modulation_input = ModulationInput::from_index(value.round() as usize)
+                    .unwrap_or_default();
+            }
+            _ => match segment_field(field) {
+                Some(("time", segment)) => self.segment_times[segment] = value,
+                Some((_, segment)) => self.segment_levels[segment] = value,
+                None => return None,
+            },
+        }
+        Some(())
+    }
+
+    /// Take on `source`'s envelope, keeping everything else
+    pub fn copy_envelope_from(&mut self, source: &OperatorParams) {
+        self.attack = source.attack;
+        self.decay = source.decay;
+        self.sustain = source.sustain;
+        self.release = source.release;
+        self.attack_curve = source.attack_curve;
+        self.decay_curve = source.decay_curve;
+        self.release_curve = source.release_curve;
+        self.rate_level = source.rate_level;
+        self.segment_times = source.segment_times;
+        self.segment_levels = source.segment_levels;
+        self.envelope_loop = source.envelope_loop;
+    }
+
+    /// Settings `amount` of the way (0.0 - 1.0) from these to `other`'s.
+    /// Ratios blend on a log scale; switches and curves take whichever side
+    /// is nearer.
+    pub fn blend(&self, other: &OperatorParams, amount: f32) -> OperatorParams {
+        let mix = |a: f32, b: f32| a + (b - a) * amount;
+        let nearer = if amount < 0.5 { self } else { other };
+        OperatorParams {
+            ratio: self.ratio * (other.ratio / self.ratio).powf(amount),
+            level: mix(self.level, other.level),
+            phase: mix(self.phase, other.phase),
+            velocity_sens: mix(self.velocity_sens, other.velocity_sens),
+            feedback: mix(self.feedback, other.feedback),
+            attack: mix(self.attack, other.attack),
+            decay: mix(self.decay, other.decay),
+            sustain: mix(self.sustain, other.sustain),
+            release: mix(self.release, other.release),
+            segment_times: std::array::from_fn(|i| mix(self.segment_times[i], other.segment_times[i])),
+            segment_levels: std::array::from_fn(|i| mix(self.segment_levels[i], other.segment_levels[i])),
+            ..*nearer
+        }
+    }
+}
+
+/// Operator settings and algorithm that take over from a patch's own
+/// towards one end of the keyboard. As parameters they take a `low_` or
+/// `high_` prefix, e.g. `low_op1_ratio` and `high_algorithm`.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyZone {
+    pub enabled: bool,
+    /// MIDI note at the centre of the crossfade into the zone
+    pub split: f32,
+    pub algorithm: usize,
+    pub operators: [OperatorParams; NUM_OPERATORS],
+}
+
+impl Default for KeyZone {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            split: 60.0,
+            algorithm: 1,
+            operators: default_operators(),
+        }
+    }
+}
+
+/// A sub-octave modulator driving a carrier, both at full level
+fn default_operators() -> [OperatorParams; NUM_OPERATORS] {
+    let mut operators = [OperatorParams::default(); NUM_OPERATORS];
+    operators[0].level = 1.0;
+    operators[1].ratio = 0.5;
+    operators[1].level = 1.0;
+    operators
+}
+
+/// FM Synthesizer parameters. Fields missing from a saved preset take
+/// their default values.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FMParams {
+    /// Note frequency in Hz; operators run at ratios of it. [`FMSynth`]
+    /// sets this per voice from the note played.
+    ///
+    /// [`FMSynth`]: crate::FMSynth
+    pub frequency: f32,
+    /// Operator settings, op 1 first
+    pub operators: [OperatorParams; NUM_OPERATORS],
+    /// Operator routing, numbered 1-8 as in [`ALGORITHMS`](crate::ALGORITHMS)
+    pub algorithm: usize,
+    /// Modulation depth: peak phase deviation in radians from a
+    /// full-level modulator
+    pub modulation_index: f32,
+    /// Output amplitude (0.0 - 1.0)
+    pub amplitude: f32,
+    /// Gain evening out levels between presets, on top of the amplitude;
+    /// set by [`Preset::auto_trim`](crate::Preset::auto_trim)
+    pub trim: f32,
+    /// Timbre envelope attack time in seconds
+    pub index_attack: f32,
+    /// Timbre envelope decay time in seconds
+    pub index_decay: f32,
+    /// Timbre envelope sustain level (0.0 - 1.0)
+    pub index_sustain: f32,
+    /// Timbre envelope release time in seconds
+    pub index_release: f32,
+    /// Timbre envelope depth on mod index (0.0 - 1.0)
+    pub index_env_amount: f32,
+    /// LFO rate in Hz
+    pub lfo_rate: f32,
+    /// LFO depth (0.0 - 1.0); 0 turns the LFO off
+    pub lfo_depth: f32,
+    /// What the LFO modulates
+    pub lfo_destination: LfoDestination,
+    /// Right channel detune against the left, in cents, for width
+    pub stereo_detune: f32,
+    /// Right channel phase lead over the left, in degrees, set at note-on
+    pub stereo_phase: f32,
+    /// Pitch bend range in semitones either way
+    pub pitch_bend_range: f32,
+    /// Extra modulation index at full mod wheel, as a fraction of the
+    /// patch's own (1.0 doubles it)
+    pub mod_wheel_depth: f32,
+    /// Modulation index burst at key-off, as a fraction of the patch's own
+    /// (1.0 doubles it), for the bright release transients of clavinets
+    /// and harpsichords
+    pub release_index: f32,
+    /// Time constant in seconds over which the key-off burst dies away
+    pub release_index_time: f32,
+    /// How much note velocity scales the whole voice's amplitude (0.0 -
+    /// 1.0), on top of any per-operator sensitivity. At 0 every note is
+    /// equally loud.
+    pub velocity_amp: f32,
+    /// How much note velocity scales the modulation index (0.0 - 1.0), so
+    /// softer notes are also darker
+    pub velocity_index: f32,
+    /// MIDI note above which key scaling starts
+    pub key_scale_break: f32,
+    /// Modulation index reduction in dB per octave above the breakpoint,
+    /// taming harsh and aliasing high notes
+    pub key_scale_index: f32,
+    /// Output level reduction in dB per octave above the breakpoint
+    pub key_scale_level: f32,
+    /// How strongly operator ratios are pulled to the nearest of the
+    /// [`SNAP_RATIOS`] (0.0 - 1.0), so ratio sweeps land on musical
+    /// intervals. At 1 ratios are fully quantized.
+    pub ratio_snap: f32,
+    /// Portamento: seconds each note takes to slide from the previous
+    /// note's pitch to its own; 0 plays notes at pitch
+    pub glide_time: f32,
+    /// Restart operator phases at note-on
+    pub phase_reset: bool,
+    /// Compute operator sines exactly rather than from the faster lookup
+    /// table, for reference renders
+    pub precise_sine: bool,
+    /// Most voices this patch may sound at once, within the synth's
+    /// global limit
+    pub polyphony: usize,
+    /// Settings for notes below `low_zone.split`
+    pub low_zone: KeyZone,
+    /// Settings for notes above `high_zone.split`
+    pub high_zone: KeyZone,
+    /// Width in semitones over which notes blend into a key zone
+    pub zone_crossfade: f32,
+}
+
+impl Default for FMParams {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            operators: default_operators(),
+            algorithm: 1,
+            modulation_index: 2.0,
+            amplitude: 0.3,
+            trim: 1.0,
+            index_attack: 0.01,
+            index_decay: 0.3,
+            index_sustain: 0.5,
+            index_release: 0.5,
+            index_env_amount: 0.0,
+            lfo_rate: 5.0,
+            lfo_depth: 0.0,
+            lfo_destination: LfoDestination::Pitch,
+            stereo_detune: 0.0,
+            stereo_phase: 0.0,
+            pitch_bend_range: 2.0,
+            mod_wheel_depth: 1.0,
+            release_index: 0.0,
+            release_index_time: 0.05,
+            velocity_amp: 0.0,
+            velocity_index: 0.0,
+            key_scale_break: 60.0,
+            key_scale_index: 0.0,
+            key_scale_level: 0.0,
+            ratio_snap: 0.0,
+            glide_time: 0.0,
+            phase_reset: false,
+            precise_sine: false,
+            polyphony: 8,
+            low_zone: KeyZone {
+                split: 48.0,
+                ..KeyZone::default()
+            },
+            high_zone: KeyZone {
+                split: 72.0,
+                ..KeyZone::default()
+            },
+            zone_crossfade: 6.0,
+        }
+    }
+}
+
+impl FMParams {
+    /// Read a parameter by its id (toggles read as 0.0 / 1.0)
+    pub fn get(&self, id: &str) -> Option<f32> {
+        if let Some((zone, id)) = zone_field(id) {
+            let zone = if zone == "low" { &self.low_zone } else { &self.high_zone };
+            return match operator_field(id) {
+                Some((index, field)) => zone.operators[index].get(field),
+                None => Some(zone.algorithm as f32),
+            };
+        }
+        if let Some((index, field)) = operator_field(id) {
+            return self.operators[index].get(field);
+        }
+        
+        let value = match id {
+            "frequency" => self.frequency,
+            "algorithm" => self.algorithm as f32,
+            "modulation_index" => self.modulation_index,
+            "amplitude" => self.amplitude,
+            "trim" => self.trim,
+            "index_attack" => self.index_attack,
+            "index_decay" => self.index_decay,
+            "index_sustain" => self.index_sustain,
+            "index_release" => self.index_release,
+            "index_env_amount" => self.index_env_amount,
+            "lfo_rate" => self.lfo_rate,
+            "lfo_depth" => self.lfo_depth,
+            "lfo_destination" => self.lfo_destination.index() as f32,
+            "stereo_detune" => self.stereo_detune,
+            "stereo_phase" => self.stereo_phase,
+            "pitch_bend_range" => self.pitch_bend_range,
+            "mod_wheel_depth" => self.mod_wheel_depth,
+            "release_index" => self.release_index,
+            "release_index_time" => self.release_index_time,
+            "velocity_amp" => self.velocity_amp,
+            "velocity_index" => self.velocity_index,
+            "key_scale_break" => self.key_scale_break,
+            "key_scale_index" => self.key_scale_index,
+            "key_scale_level" => self.key_scale_level,
+            "ratio_snap" => self.ratio_snap,
+            "glide_time" => self.glide_time,
+            "phase_reset" => if self.phase_reset { 1.0 } else { 0.0 },
+            "precise_sine" => if self.precise_sine { 1.0 } else { 0.0 },
+            "polyphony" => self.polyphony as f32,
+            "low_zone" => if self.low_zone.enabled { 1.0 } else { 0.0 },
+            "low_zone_split" => self.low_zone.split,
+            "high_zone" => if self.high_zone.enabled { 1.0 } else { 0.0 },
+            "high_zone_split" => self.high_zone.split,
+            "zone_crossfade" => self.zone_crossfade,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Write a parameter by its id after validating it against its range
+    pub fn set(&mut self, id: &str, value: f32) -> Result<(), ParamError> {
+        Parameter::find(id)
+            .ok_or_else(|| ParamError::Unknown(id.to_string()))?
+            .validate(value)?;
+        
+        let unknown = || ParamError::Unknown(id.to_string());
+        if let Some((zone, id)) = zone_field(id) {
+            let zone = if zone == "low" { &mut self.low_zone } else { &mut self.high_zone };
+            match operator_field(id) {
+                Some((index, field)) => zone.operators[index].set(field, value).ok_or_else(unknown)?,
+                None => zone.algorithm = value.round() as usize,
+            }
+            return Ok(());
+        }
+        if let Some((index, field)) = operator_field(id) {
+            return self.operators[index].set(field, value).ok_or_else(unknown);
+        }
+        
+        match id {
+            "frequency" => self.frequency = value,
+            "algorithm" => self.algorithm = value.round() as usize,
+            "modulation_index" => self.modulation_index = value,
+            "amplitude" => self.amplitude = value,
+            "trim" => self.trim = value,
+            "index_attack" => self.index_attack = value,
+            "index_decay" => self.index_decay = value,
+            "index_sustain" => self.index_sustain = value,
+            "index_release" => self.index_release = value,
+            "index_env_amount" => self.index_env_amount = value,
+            "lfo_rate" => self.lfo_rate = value,
+            "lfo_depth" => self.lfo_depth = value,
+            "lfo_destination" => {
+                self.lfo_destination = LfoDestination::from_index(value.round() as usize)
+                    .unwrap_or(LfoDestination::Pitch);
+            }
+            "stereo_detune" => self.stereo_detune = value,
+            "stereo_phase" => self.stereo_phase = value,
+            "pitch_bend_range" => self.pitch_bend_range = value.round(),
+            "mod_wheel_depth" => self.mod_wheel_depth = value,
+            "release_index" => self.release_index = value,
+            "release_index_time" => self.release_index_time = value,
+            "velocity_amp" => self.velocity_amp = value,
+            "velocity_index" => self.velocity_index = value,
+            "key_scale_break" => self.key_scale_break = value.round(),
+            "key_scale_index" => self.key_scale_index = value,
+            "key_scale_level" => self.key_scale_level = value,
+            "ratio_snap" => self.ratio_snap = value,
+            "glide_time" => self.glide_time = value,
+            "phase_reset" => self.phase_reset = value >= 0.5,
+            "precise_sine" => self.precise_sine = value >= 0.5,
+            "polyphony" => self.polyphony = value.round() as usize,
+            "low_zone" => self.low_zone.enabled = value >= 0.5,
+            "low_zone_split" => self.low_zone.split = value.round(),
+            "high_zone" => self.high_zone.enabled = value >= 0.5,
+            "high_zone_split" => self.high_zone.split = value.round(),
+            "zone_crossfade" => self.zone_crossfade = value.round(),
+            _ => return Err(ParamError::Unknown(id.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Copy every setting of operator `from` onto operator `to` (numbered
+    /// from 0)
+    pub fn copy_operator(&mut self, from: usize, to: usize) {
+        self.operators[to] = self.operators[from];
+    }
+
+    /// Give every operator the envelope of operator `from` (numbered from 0)
+    pub fn copy_envelope_to_all(&mut self, from: usize) {
+        let source = self.operators[from];
+        for op in &mut self.operators {
+            op.copy_envelope_from(&source);
+        }
+    }
+
+    /// Frequency ratio operator `index` (numbered from 0) actually runs at,
+    /// after [`ratio_snap`](Self::ratio_snap). Ratios are compared and
+    /// blended on a log scale, as pitch is heard.
+    pub fn snapped_ratio(&self, index: usize) -> f32 {
+        let ratio = self.operators[index].ratio;
+        if self.ratio_snap <= 0.0 {
+            return ratio;
+        }
+        let nearest = SNAP_RATIOS.into_iter()
+            .min_by(|a, b| (ratio / a).ln().abs().total_cmp(&(ratio / b).ln().abs()))
+            .unwrap_or(ratio);
+        ratio * (nearest / ratio).powf(self.ratio_snap.min(1.0))
+    }
+
+    /// The patch as heard on `note`: inside an enabled key zone its
+    /// operators and algorithm replace the patch's own, blending across
+    /// the crossfade. The algorithm switches halfway through it.
+    pub fn for_note(&self, note: u8) -> FMParams {
+        let mut params = self.clone();
+        let half = self.zone_crossfade / 2.0;
+        for (zone, semitones_in) in [
+            (&self.low_zone, self.low_zone.split - note as f32),
+            (&self.high_zone, note as f32 - self.high_zone.split),
+        ] {
+            if !zone.enabled {
+                continue;
+            }
+            let amount = if half > 0.0 {
+                ((semitones_in + half) / (2.0 * half)).clamp(0.0, 1.0)
+            } else if semitones_in >= 0.0 {
+                1.0
+            } else {
+                0.0
+            };
+            if amount > 0.0 {
+                for (op, zone_op) in params.operators.iter_mut().zip(&zone.operators) {
+                    *op = op.blend(zone_op, amount);
+                }
+                if amount >= 0.5 {
+                    params.algorithm = zone.algorithm;
+                }
+            }
+        }
+        params
+    }
+
+    /// Move the note to `freq` Hz, clamped to range. Operators follow
+    /// through their ratios, so the timbre is kept.
+    pub fn retune(&mut self, freq: f32) {
+        self.frequency = clamp_param("frequency", freq);
+    }
+
+    /// Check every parameter against its range
+    pub fn validate(&self) -> Result<(), ParamError> {
+        for param in PARAMETERS {
+            if let Some(value) = self.get(param.id) {
+                param.validate(value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a key zone id such as "low_op2_ratio" or "high_algorithm" into
+/// the zone, "low" or "high", and the id within it
+fn zone_field(id: &str) -> Option<(&str, &str)> {
+    let (zone, id) = id.split_once('_')?;
+    let zoned = id == "algorithm" || operator_field(id).is_some();
+    (matches!(zone, "low" | "high") && zoned).then_some((zone, id))
+}
+
+/// Split an operator id such as "op2_ratio" into (index, field)
+fn operator_field(id: &str) -> Option<(usize, &str)> {
+    let (number, field) = id.strip_prefix("op")?.split_once('_')?;
+    let index = number.parse::<usize>().ok()?.checked_sub(1)?;
+    (index < NUM_OPERATORS).then_some((index, field))
+}
+
+/// Split a rate/level envelope field such as "time2" or "level4" into
+/// ("time" or "level", segment index)
+fn segment_field(field: &str) -> Option<(&str, usize)> {
+    let (name, number) = field.split_at_checked(field.len().checked_sub(1)?)?;
+    let segment = number.parse::<usize>().ok()?.checked_sub(1)?;
+    (matches!(name, "time" | "level") && segment < 4).then_some((name, segment))
+}
+
+fn clamp_param(id: &str, value: f32) -> f32 {
+    Parameter::find(id).map_or(value, |param| param.clamp(value))
+}
+
+/// Error returned when an externally supplied parameter is rejected
+#[derive(Debug)]
+pub enum ParamError {
+    /// No parameter has this id
+    Unknown(String),
+    /// NaN or infinite value
+    NotFinite { name: &'static str },
+    /// Value outside the parameter's range, formatted in its display unit
+    OutOfRange { name: &'static str, value: String, min: String, max: String },
+}
+
+impl std::fmt::Display for ParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamError::Unknown(id) => write!(f, "Unknown parameter '{}'", id),
+            ParamError::NotFinite { name } => write!(f, "{} must be a finite number", name),
+            ParamError::OutOfRange { name, value, min, max } => {
+                write!(f, "{} out of range: {} (allowed {} to {})", name, value, min, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// Unit a parameter is displayed and entered in
+#[derive(Clone, Copy, PartialEq)]
+pub enum Unit {
+    Hz,
+    /// Plain multiplier, e.g. modulation index
+    Ratio,
+    /// Stored as linear gain
+    Decibels,
+    /// Stored in seconds
+    Millis,
+    /// Stored as 0.0 - 1.0
+    Percent,
+    Degrees,
+    /// Hundredths of a semitone
+    Cents,
+    /// Slope of a key scaling curve
+    DecibelsPerOctave,
+    /// Whole numbers, e.g. algorithm number
+    Integer,
+    /// Stored as 0.0 (off) / 1.0 (on)
+    Toggle,
+    /// Stored as an index into these names
+    Choice(&'static [&'static str]),
+}
+
+/// Metadata for one externally editable parameter, shared by every
+/// frontend so values are shown and entered consistently
+pub struct Parameter {
+    pub id: &'static str,
+    pub name: &'static str,
+    /// Lower bound in stored (not display) units
+    pub min: f32,
+    /// Upper bound in stored units
+    pub max: f32,
+    pub unit: Unit,
+}
+
+/// Build a parameter table, writing each `@operators` row once for every
+/// prefix and operator listed: with prefix `("low_" "Low ")`, the row for
+/// suffix `ratio` and name `Ratio` becomes `low_op1_ratio`, "Low Op1
+/// Ratio" and so on
+macro_rules! parameters {
+    (
+        $($before:expr,)*
+        @operators [$($prefix:tt)*] [$($op:literal)*] { $($rows:tt)* }
+        $($after:expr,)*
+    ) => {
+        parameters!(@expand [$($before,)*] [$($prefix)*] [$($op)*] [$($op)*] { $($rows)* } [$($after,)*])
+    };
+    (@expand [$($done:expr,)*] [] $ops:tt $all:tt { $($rows:tt)* } [$($after:expr,)*]) => {
+        &[$($done,)* $($after,)*]
+    };
+    (
+        @expand $done:tt [$prefix:tt $($prefixes:tt)*] [] [$($all:literal)*]
+        { $($rows:tt)* } $after:tt
+    ) => {
+        parameters!(@expand $done [$($prefixes)*] [$($all)*] [$($all)*] { $($rows)* } $after)
+    };
+    (
+        @expand [$($done:expr,)*] [($id:literal $label:literal) $($prefixes:tt)*]
+        [$op:literal $($ops:literal)*] $all:tt
+        { $($suffix:literal, $name:literal, $min:expr, $max:expr, $unit:expr;)* }
+        $after:tt
+    ) => {
+        parameters!(
+            @expand
+            [
+                $($done,)*
+                $(Parameter {
+                    id: concat!($id, "op", $op, "_", $suffix),
+                    name: concat!($label, "Op", $op, " ", $name),
+                    min: $min,
+                    max: $max,
+                    unit: $unit,
+                },)*
+            ]
+            [($id $label) $($prefixes)*]
+            [$($ops)*]
+            $all
+            { $($suffix, $name, $min, $max, $unit;)* }
+            $after
+        )
+    };
+}
+
+/// Every parameter settable from outside the engine
+pub const PARAMETERS: &[Parameter] = parameters! {
+    Parameter { id: "frequency", name: "Frequency", min: 20.0, max: 20000.0, unit: Unit::Hz },
+    Parameter { id: "algorithm", name: "Algorithm", min: 1.0, max: 8.0, unit: Unit::Integer },
+    // The patch's own operators, then the key zones'
+    @operators [("" "") ("low_" "Low ") ("high_" "High ")] [1 2 3 4] {
+        "ratio", "Ratio", 0.01, 32.0, Unit::Ratio;
+        "level", "Level", 0.0, 1.0, Unit::Percent;
+        "phase", "Phase", 0.0, 360.0, Unit::Degrees;
+        "velocity", "Vel Sens", 0.0, 1.0, Unit::Percent;
+        "feedback", "Feedback", 0.0, 7.0, Unit::Ratio;
+        "attack", "Attack", 0.001, 10.0, Unit::Millis;
+        "decay", "Decay", 0.001, 10.0, Unit::Millis;
+        "sustain", "Sustain", 0.0, 1.0, Unit::Percent;
+        "release", "Release", 0.001, 10.0, Unit::Millis;
+        "attack_curve", "Atk Curve", 0.0, 2.0, Unit::Choice(EnvelopeCurve::NAMES);
+        "decay_curve", "Dec Curve", 0.0, 2.0, Unit::Choice(EnvelopeCurve::NAMES);
+        "release_curve", "Rel Curve", 0.0, 2.0, Unit::Choice(EnvelopeCurve::NAMES);
+        "rate_level", "Rate/Level", 0.0, 1.0, Unit::Toggle;
+        "time1", "Time 1", 0.001, 40.0, Unit::Millis;
+        "time2", "Time 2", 0.001, 40.0, Unit::Millis;
+        "time3", "Time 3", 0.001, 40.0, Unit::Millis;
+        "time4", "Time 4", 0.001, 40.0, Unit::Millis;
+        "level1", "Level 1", 0.0, 1.0, Unit::Percent;
+        "level2", "Level 2", 0.0, 1.0, Unit::Percent;
+        "level3", "Level 3", 0.0, 1.0, Unit::Percent;
+        "level4", "Level 4", 0.0, 1.0, Unit::Percent;
+        "loop", "Env Loop", 0.0, 2.0, Unit::Choice(EnvelopeLoop::NAMES);
+        "input", "FM Input", 0.0, 1.0, Unit::Choice(ModulationInput::NAMES);
+    }
+    Parameter { id: "modulation_index", name: "Mod Index", min: 0.0, max: 20.0, unit: Unit::Ratio },
+    Parameter { id: "amplitude", name: "Level", min: 0.0, max: 1.0, unit: Unit::Decibels },
+    Parameter { id: "trim", name: "Trim", min: 0.0, max: 4.0, unit: Unit::Decibels },
+    Parameter { id: "index_attack", name: "Timbre Attack", min: 0.001, max: 10.0, unit: Unit::Millis },
+    Parameter { id: "index_decay", name: "Timbre Decay", min: 0.001, max: 10.0, unit: Unit::Millis },
+    Parameter { id: "index_sustain", name: "Timbre Sustain", min: 0.0, max: 1.0, unit: Unit::Percent },
+    Parameter { id: "index_release", name: "Timbre Release", min: 0.001, max: 10.0, unit: Unit::Millis },
+    Parameter { id: "index_env_amount", name: "Timbre Amount", min: 0.0, max: 1.0, unit: Unit::Percent },
+    Parameter { id: "lfo_rate", name: "LFO Rate", min: 0.01, max: 50.0, unit: Unit::Hz },
+    Parameter { id: "lfo_depth", name: "LFO Depth", min: 0.0, max: 1.0, unit: Unit::Percent },
+    Parameter { id: "lfo_destination", name: "LFO Dest", min: 0.0, max: 2.0, unit: Unit::Choice(LfoDestination::NAMES) },
+    Parameter { id: "stereo_detune", name: "Stereo Detune", min: 0.0, max: 50.0, unit: Unit::Cents },
+    Parameter { id: "stereo_phase", name: "Stereo Phase", min: 0.0, max: 180.0, unit: Unit::Degrees },
+    Parameter { id: "pitch_bend_range", name: "Bend Range", min: 0.0, max: 24.0, unit: Unit::Integer },
+    Parameter { id: "mod_wheel_depth", name: "Wheel Depth", min: 0.0, max: 4.0, unit: Unit::Percent },
+    Parameter { id: "release_index", name: "Release Index", min: 0.0, max: 4.0, unit: Unit::Percent },
+    Parameter { id: "release_index_time", name: "Release Idx Time", min: 0.001, max: 2.0, unit: Unit::Millis },
+    Parameter { id: "velocity_amp", name: "Vel Amp", min: 0.0, max: 1.0, unit: Unit::Percent },
+    Parameter { id: "velocity_index", name: "Vel Index", min: 0.0, max: 1.0, unit: Unit::Percent },
+    Parameter { id: "key_scale_break", name: "Key Break", min: 0.0, max: 127.0, unit: Unit::Integer },
+    Parameter { id: "key_scale_index", name: "Key Index", min: 0.0, max: 24.0, unit: Unit::DecibelsPerOctave },
+    Parameter { id: "key_scale_level", name: "Key Level", min: 0.0, max: 24.0, unit: Unit::DecibelsPerOctave },
+    Parameter { id: "ratio_snap", name: "Ratio Snap", min: 0.0, max: 1.0, unit: Unit::Percent },
+    Parameter { id: "glide_time", name: "Glide", min: 0.0, max: 5.0, unit: Unit::Millis },
+    Parameter { id: "phase_reset", name: "Phase Reset", min: 0.0, max: 1.0, unit: Unit::Toggle },
+    Parameter { id: "precise_sine", name: "Precise Sine", min: 0.0, max: 1.0, unit: Unit::Toggle },
+    Parameter { id: "polyphony", name: "Polyphony", min: 1.0, max: 32.0, unit: Unit::Integer },
+    Parameter { id: "low_zone", name: "Low Zone", min: 0.0, max: 1.0, unit: Unit::Toggle },
+    Parameter { id: "low_zone_split", name: "Low Split", min: 0.0, max: 127.0, unit: Unit::Integer },
+    Parameter { id: "high_zone", name: "High Zone", min: 0.0, max: 1.0, unit: Unit::Toggle },
+    Parameter { id: "high_zone_split", name: "High Split", min: 0.0, max: 127.0, unit: Unit::Integer },
+    Parameter { id: "zone_crossfade", name: "Zone Fade", min: 0.0, max: 24.0, unit: Unit::Integer },
+    Parameter { id: "low_algorithm", name: "Low Algorithm", min: 1.0, max: 8.0, unit: Unit::Integer },
+    Parameter { id: "high_algorithm", name: "High Algorithm", min: 1.0, max: 8.0, unit: Unit::Integer },
+};
+
+impl Parameter {
+    /// Look up a parameter by id
+    pub fn find(id: &str) -> Option<&'static Parameter> {
+        PARAMETERS.iter().find(|p| p.id == id)
+    }
+
+    /// Reject NaN/infinite and out-of-range values
+    pub fn validate(&self, value: f32) -> Result<(), ParamError> {
+        if !value.is_finite() {
+            return Err(ParamError::NotFinite { name: self.name });
+        }
+        if value < self.min || value > self.max {
+            return Err(ParamError::OutOfRange {
+                name: self.name,
+                value: self.format(value),
+                min: self.format(self.min),
+                max: self.format(self.max),
+            });
+        }
+        Ok(())
+    }
+
+    /// Pull a value into range (NaN becomes the minimum)
+    pub fn clamp(&self, value: f32) -> f32 {
+        if value.is_nan() {
+            self.min
+        } else {
+            value.clamp(self.min, self.max)
+        }
+    }
+
+    pub fn default_value(&self) -> f32 {
+        FMParams::default().get(self.id).unwrap_or(self.min)
+    }
+
+    /// Format a stored value in this parameter's display unit
+    pub fn format(&self, value: f32) -> String {
+        match self.unit {
+            Unit::Hz if value >= 1000.0 => format!("{:.2} kHz", value / 1000.0),
+            Unit::Hz => format!("{:.1} Hz", value),
+            Unit::Ratio => format!("{:.2}", value),
+            Unit::Decibels if value <= 0.0 => "-inf dB".to_string(),
+            Unit::Decibels => format!("{:.1} dB", 20.0 * value.log10()),
+            Unit::Millis => format!("{:.0} ms", value * 1000.0),
+            Unit::Percent => format!("{:.0}%", value * 100.0),
+            Unit::Degrees => format!("{:.0} deg", value),
+            Unit::Cents => format!("{:.1} cents", value),
+            Unit::DecibelsPerOctave => format!("{:.1} dB/oct", value),
+            Unit::Integer => format!("{:.0}", value),
+            Unit::Toggle => if value >= 0.5 { "on" } else { "off" }.to_string(),
+            Unit::Choice(names) => names.get(value.round() as usize).unwrap_or(&"?").to_string(),
+        }
+    }
+
+    /// Parse user text such as "440", "1.2 kHz", "-6 dB", "-inf dB", "250
+    /// ms", "on" or a choice name into a stored value, clamped to the
+    /// parameter's range. Bare numbers are taken to be in the display unit.
+    pub fn parse(&self, text: &str) -> Option<f32> {
+        let text = text.trim().to_ascii_lowercase();
+        
+        if self.unit == Unit::Toggle {
+            return match text.as_str() {
+                "on" | "true" | "1" => Some(1.0),
+                "off" | "false" | "0" => Some(0.0),
+                _ => None,
+            };
+        }
+        if let Unit::Choice(names) = self.unit {
+            let index = names.iter()
+                .position(|name| *name == text)
+                .or_else(|| text.parse().ok())?;
+            return Some(self.clamp(index as f32));
+        }
+        // Silence, as formatted
+        if self.unit == Unit::Decibels && matches!(text.as_str(), "-inf" | "-inf db") {
+            return Some(self.clamp(0.0));
+        }
+        
+        // Split into number and unit suffix
+        let split = text
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+            .unwrap_or(text.len());
+        let number: f32 = text[..split].parse().ok()?;
+        let suffix = text[split..].trim();
+        
+        let value = match (self.unit, suffix) {
+            (Unit::Hz, "" | "hz") => number,
+            (Unit::Hz, "khz") => number * 1000.0,
+            (Unit::Ratio, "" | "x") => number,
+            (Unit::Decibels, "" | "db") => 10.0_f32.powf(number / 20.0),
+            (Unit::Millis, "" | "ms") => number / 1000.0,
+            (Unit::Millis, "s") => number,
+            (Unit::Percent, "" | "%") => number / 100.0,
+            (Unit::Degrees, "" | "deg") => number,
+            (Unit::Cents, "" | "c" | "cents") => number,
+            (Unit::DecibelsPerOctave, "" | "db" | "db/oct") => number,
+            (Unit::Integer, "") => number.round(),
+            _ => return None,
+        };
+        Some(self.clamp(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `text` for parameter `id`, checking it formats back the same
+    fn round_trip(id: &str, text: &str, formatted: &str) -> f32 {
+        let param = Parameter::find(id).unwrap();
+        let value = param.parse(text).unwrap();
+        assert_eq!(param.format(value), formatted);
+        let again = param.parse(formatted).unwrap();
+        assert!((again - value).abs() <= value.abs() * 1e-5, "{} became {}", value, again);
+        value
+    }
+
+    #[test]
+    fn parse_and_format_round_trip() {
+        assert_eq!(round_trip("frequency", "1.2 kHz", "1.20 kHz"), 1200.0);
+        assert_eq!(round_trip("frequency", "440", "440.0 Hz"), 440.0);
+        let gain = round_trip("amplitude", "-6 dB", "-6.0 dB");
+        assert!((gain - 0.501).abs() < 1e-3);
+        assert_eq!(round_trip("amplitude", "-inf dB", "-inf dB"), 0.0);
+        assert_eq!(round_trip("amplitude", "-inf", "-inf dB"), 0.0);
+        assert_eq!(round_trip("op3_attack", "250 ms", "250 ms"), 0.25);
+        assert_eq!(round_trip("op3_attack", "0.5 s", "500 ms"), 0.5);
+        assert_eq!(round_trip("op2_input", "exp", "exp"), 1.0);
+        assert_eq!(round_trip("phase_reset", "on", "on"), 1.0);
+    }
+
+    #[test]
+    fn parse_rejects_wrong_units() {
+        let parse = |id: &str, text: &str| Parameter::find(id).unwrap().parse(text);
+        assert_eq!(parse("frequency", "250 ms"), None);
+        assert_eq!(parse("amplitude", "1.2 kHz"), None);
+        assert_eq!(parse("op1_attack", "-6 dB"), None);
+        assert_eq!(parse("algorithm", "3 x"), None);
+        assert_eq!(parse("frequency", "loud"), None);
+        assert_eq!(parse("op1_input", "linear"), None);
+    }
+
+    #[test]
+    fn zone_parameters() {
+        let mut params = FMParams::default();
+        params.set("low_op2_ratio", 3.0).unwrap();
+        params.set("high_algorithm", 5.0).unwrap();
+        params.set("high_op4_loop", 2.0).unwrap();
+        assert_eq!(params.low_zone.operators[1].ratio, 3.0);
+        assert_eq!(params.high_zone.algorithm, 5);
+        assert!(params.high_zone.operators[3].envelope_loop == EnvelopeLoop::FromFirst);
+        assert_eq!(params.get("low_op2_ratio"), Some(3.0));
+        assert_eq!(params.get("low_zone_split"), Some(params.low_zone.split));
+        assert_eq!(Parameter::find("high_op3_attack").unwrap().name, "High Op3 Attack");
+        assert!(params.set("low_op1_ratio", 100.0).is_err());
+
+        params.low_zone.operators[0].level = 2.0;
+        assert!(matches!(params.validate(), Err(ParamError::OutOfRange { name: "Low Op1 Level", .. })));
+    }
+
+    #[test]
+    fn operator_rows_follow_the_operator() {
+        let param = Parameter::find("op4_release_curve").unwrap();
+        assert_eq!(param.name, "Op4 Rel Curve");
+        assert!(param.unit == Unit::Choice(EnvelopeCurve::NAMES));
+        let count = |n: usize| {
+            let prefix = format!("op{}_", n);
+            PARAMETERS.iter().filter(|p| p.id.starts_with(&prefix)).count()
+        };
+        assert!((2..=NUM_OPERATORS).all(|n| count(n) == count(1)));
+    }
+}
@@ -0,0 +1,127 @@
+//! Named patches saved to and loaded from JSON files
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{FMParams, FMSynth, ParamError, Parameter, peak};
+
+/// Peak level in dBFS that [`Preset::auto_trim`] brings presets to
+pub const TRIM_TARGET_DB: f32 = -6.0;
+
+/// Notes measured by [`Preset::measure_peak`]: every C from C1 to C8
+const MEASURE_NOTES: [u8; 8] = [24, 36, 48, 60, 72, 84, 96, 108];
+
+/// Velocities measured at each note
+const MEASURE_VELOCITIES: [f32; 3] = [0.25, 0.6, 1.0];
+
+/// Seconds each measured note is held, then rendered after release
+const MEASURE_HOLD: f32 = 0.5;
+const MEASURE_RELEASE: f32 = 0.25;
+
+/// A named patch, stored on disk as JSON
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub params: FMParams,
+}
+
+impl Preset {
+    pub fn new(name: impl Into<String>, params: FMParams) -> Self {
+        Self {
+            name: name.into(),
+            params,
+        }
+    }
+
+    /// Write the preset to `path` as pretty-printed JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), PresetError> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Read a preset from `path`, rejecting out-of-range parameters
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PresetError> {
+        let reader = BufReader::new(File::open(path)?);
+        let preset: Preset = serde_json::from_reader(reader)?;
+        preset.params.validate()?;
+        Ok(preset)
+    }
+
+    /// Worst-case peak of single notes across the keyboard and velocity
+    /// range, rendered at `sample_rate` without the preset's trim. Chords
+    /// peak higher, by up to the number of voices.
+    pub fn measure_peak(&self, sample_rate: f32) -> Result<f32, ParamError> {
+        let params = FMParams { trim: 1.0, ..self.params.clone() };
+        let mut worst: f32 = 0.0;
+        let mut left = vec![0.0; (MEASURE_HOLD * sample_rate) as usize];
+        let mut right = left.clone();
+        for note in MEASURE_NOTES {
+            for velocity in MEASURE_VELOCITIES {
+                let mut synth = FMSynth::new(sample_rate, params.clone())?;
+                synth.note_on(note, velocity);
+                synth.process_stereo(&mut left, &mut right);
+                worst = worst.max(peak(&left)).max(peak(&right));
+                
+                synth.note_off(note);
+                let release = (MEASURE_RELEASE * sample_rate) as usize;
+                synth.process_stereo(&mut left[..release], &mut right[..release]);
+                worst = worst.max(peak(&left[..release])).max(peak(&right[..release]));
+            }
+        }
+        Ok(worst)
+    }
+
+    /// Set the trim so the preset's worst-case single-note peak sits at
+    /// [`TRIM_TARGET_DB`], within the trim's range, so switching presets
+    /// live doesn't jump in level. Returns the untrimmed peak.
+    pub fn auto_trim(&mut self, sample_rate: f32) -> Result<f32, ParamError> {
+        let worst = self.measure_peak(sample_rate)?;
+        let target = 10.0_f32.powf(TRIM_TARGET_DB / 20.0);
+        let trim = if worst > 0.0 { target / worst } else { 1.0 };
+        self.params.trim = Parameter::find("trim").map_or(trim, |param| param.clamp(trim));
+        Ok(worst)
+    }
+}
+
+/// Error returned when a preset file can't be saved or loaded
+#[derive(Debug)]
+pub enum PresetError {
+    Io(std::io::Error),
+    /// Malformed JSON or wrongly typed fields
+    Format(serde_json::Error),
+    Invalid(ParamError),
+}
+
+impl std::fmt::Display for PresetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PresetError::Io(err) => write!(f, "{}", err),
+            PresetError::Format(err) => write!(f, "Invalid preset file: {}", err),
+            PresetError::Invalid(err) => write!(f, "Invalid preset: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PresetError {}
+
+impl From<std::io::Error> for PresetError {
+    fn from(err: std::io::Error) -> Self {
+        PresetError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for PresetError {
+    fn from(err: serde_json::Error) -> Self {
+        PresetError::Format(err)
+    }
+}
+
+impl From<ParamError> for PresetError {
+    fn from(err: ParamError) -> Self {
+        PresetError::Invalid(err)
+    }
+}
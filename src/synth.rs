@@ -0,0 +1,616 @@
+//! Polyphonic FM synth: a pool of voices sharing one patch
+
+use std::sync::Arc;
+
+use crate::voice::Voice;
+use crate::{
+    CC_MOD_WHEEL, Command, Decimator, MAX_OVERSAMPLING, bend_amount, FMParams, KeyedTuning,
+    MidiMessage, ParamError, Retired, Tuning,
+};
+
+/// Global voice limit used by [`FMSynth::new`]
+pub const DEFAULT_MAX_VOICES: usize = 16;
+
+/// Blocks each voice renders during [`FMSynth::warm_up`]
+const WARM_UP_BLOCKS: usize = 4;
+
+/// Samples per warm-up block
+const WARM_UP_BLOCK_LEN: usize = 256;
+
+/// Shortest parameter ramp in seconds, long enough that changes made
+/// between small blocks don't click
+const MIN_RAMP_SECONDS: f32 = 0.005;
+
+/// Output frames rendered per pass when oversampling
+const OVERSAMPLED_CHUNK: usize = 256;
+
+/// Longest parameter ramp in seconds, so rendering in very long blocks
+/// doesn't smear parameter changes
+const MAX_RAMP_SECONDS: f32 = 0.02;
+
+/// Which busy voice [`FMSynth::note_on`] takes over when none is free
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StealPolicy {
+    /// The voice started longest ago
+    Oldest,
+    /// The voice sounding most quietly
+    Quietest,
+    /// A voice already playing the same note, else the oldest
+    SameNote,
+    /// None: the new note is dropped
+    None,
+}
+
+impl StealPolicy {
+    pub const ALL: [StealPolicy; 4] = [
+        StealPolicy::Oldest,
+        StealPolicy::Quietest,
+        StealPolicy::SameNote,
+        StealPolicy::None,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StealPolicy::Oldest => "oldest",
+            StealPolicy::Quietest => "quietest",
+            StealPolicy::SameNote => "same",
+            StealPolicy::None => "none",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// One voice's share of a block, passed to the tap of
+/// [`FMSynth::process_stereo_tapped`] before it is mixed in
+pub struct VoiceOutput<'a> {
+    /// Position of the voice in the pool, stable for as long as a note
+    /// sounds on it
+    pub voice: usize,
+    /// Note the voice is playing or releasing
+    pub note: u8,
+    /// Whether the note has been released and is dying away
+    pub released: bool,
+    /// The voice's samples, at the voice rate: [`FMSynth::oversampling`]
+    /// times the output rate. Changes made here are what gets mixed.
+    pub left: &'a mut [f32],
+    pub right: &'a mut [f32],
+}
+
+/// Polyphonic FM Synthesizer with per-operator and timbre envelopes
+pub struct FMSynth {
+    sample_rate: f32,
+    params: Arc<FMParams>,  // Shared with the controller that built it
+    voices: Vec<Voice>,  // Global pool; the patch may use fewer
+    note_count: u64,     // Note-ons so far, used to age voices
+    tuning: Box<dyn Tuning>,
+    bend: f32,           // Pitch bend position (-1.0 - 1.0)
+    wheel: f32,          // Mod wheel position (0.0 - 1.0)
+    last_frequency: Option<f32>,  // Pitch of the latest note-on, where glides start
+    block_len: usize,    // Length of the last block rendered, over which changes ramp
+    steal_policy: StealPolicy,
+    switch_time: f32,            // Crossfade in seconds for switch_params
+    decimators: [Decimator; 2],  // Per channel; their factor is the oversampling
+    scratch: [Vec<f32>; 2],      // Oversampled chunk per channel
+    tap_scratch: [Vec<f32>; 2],  // One voice's chunk, for process_stereo_tapped
+}
+
+impl FMSynth {
+    /// Create a synth running at `sample_rate` Hz, rejecting out-of-range parameters
+    pub fn new(sample_rate: f32, params: FMParams) -> Result<Self, ParamError> {
+        params.validate()?;
+        let voices = (0..DEFAULT_MAX_VOICES)
+            .map(|_| Voice::new(sample_rate, &params))
+            .collect();
+        Ok(Self {
+            sample_rate,
+            params: Arc::new(params),
+            voices,
+            note_count: 0,
+            tuning: Box::new(KeyedTuning::default()),
+            bend: 0.0,
+            wheel: 0.0,
+            last_frequency: None,
+            block_len: 0,
+            steal_policy: StealPolicy::Oldest,
+            switch_time: 0.0,
+            decimators: [Decimator::new(1), Decimator::new(1)],
+            scratch: std::array::from_fn(|_| vec![0.0; OVERSAMPLED_CHUNK * MAX_OVERSAMPLING]),
+            tap_scratch: std::array::from_fn(|_| vec![0.0; OVERSAMPLED_CHUNK * MAX_OVERSAMPLING]),
+        })
+    }
+
+    /// Render the next output sample, mixing every sounding voice to mono
+    pub fn next_sample(&mut self) -> f32 {
+        self.block_len = 1;
+        let mut block = [0.0; MAX_OVERSAMPLING];
+        let block = &mut block[..self.oversampling()];
+        for sample in block.iter_mut() {
+            *sample = self.voices.iter_mut()
+                .filter(|voice| voice.is_active())
+                .map(Voice::next_sample)
+                .sum();
+        }
+        self.decimators[0].process(block)[0]
+    }
+
+    /// Render a block of output, overwriting `output`. Equivalent to
+    /// calling [`next_sample`](Self::next_sample) once per sample, but
+    /// voices render a whole block at a time.
+    pub fn process(&mut self, output: &mut [f32]) {
+        self.block_len = output.len();
+        let factor = self.oversampling();
+        if factor == 1 {
+            output.fill(0.0);
+            for voice in self.voices.iter_mut().filter(|voice| voice.is_active()) {
+                voice.process(output);
+            }
+            return;
+        }
+        
+        for chunk in output.chunks_mut(OVERSAMPLED_CHUNK) {
+            let block = &mut self.scratch[0][..chunk.len() * factor];
+            block.fill(0.0);
+            for voice in self.voices.iter_mut().filter(|voice| voice.is_active()) {
+                voice.process(block);
+            }
+            chunk.copy_from_slice(self.decimators[0].process(block));
+        }
+    }
+
+    /// Render a stereo block, overwriting `left` and `right`. Patches
+    /// without stereo width give identical channels.
+    pub fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.block_len = left.len();
+        let factor = self.oversampling();
+        if factor == 1 {
+            left.fill(0.0);
+            right.fill(0.0);
+            for voice in self.voices.iter_mut().filter(|voice| voice.is_active()) {
+                voice.process_stereo(left, right);
+            }
+            return;
+        }
+        
+        let chunks = left.chunks_mut(OVERSAMPLED_CHUNK).zip(right.chunks_mut(OVERSAMPLED_CHUNK));
+        for (chunk_l, chunk_r) in chunks {
+            let [scratch_l, scratch_r] = &mut self.scratch;
+            let block_l = &mut scratch_l[..chunk_l.len() * factor];
+            let block_r = &mut scratch_r[..chunk_r.len() * factor];
+            block_l.fill(0.0);
+            block_r.fill(0.0);
+            for voice in self.voices.iter_mut().filter(|voice| voice.is_active()) {
+                voice.process_stereo(block_l, block_r);
+            }
+            let [decimator_l, decimator_r] = &mut self.decimators;
+            chunk_l.copy_from_slice(decimator_l.process(block_l));
+            chunk_r.copy_from_slice(decimator_r.process(block_r));
+        }
+    }
+
+    /// Render a stereo block like [`process_stereo`](Self::process_stereo),
+    /// handing each sounding voice's output to `tap` before it is mixed,
+    /// for per-note processing or analysis the mixer doesn't do. Voices
+    /// are rendered in chunks of up to 256 output frames, so `tap` may be
+    /// called several times per voice for long blocks. `tap` runs on the
+    /// rendering thread and should neither block nor allocate there.
+    pub fn process_stereo_tapped(
+        &mut self,
+        left: &mut [f32],
+        right: &mut [f32],
+        mut tap: impl FnMut(VoiceOutput<'_>),
+    ) {
+        self.block_len = left.len();
+        let factor = self.oversampling();
+        let chunks = left.chunks_mut(OVERSAMPLED_CHUNK).zip(right.chunks_mut(OVERSAMPLED_CHUNK));
+        for (chunk_l, chunk_r) in chunks {
+            let len = chunk_l.len() * factor;
+            let [scratch_l, scratch_r] = &mut self.scratch;
+            let [voice_l, voice_r] = &mut self.tap_scratch;
+            let (block_l, block_r) = (&mut scratch_l[..len], &mut scratch_r[..len]);
+            let (voice_l, voice_r) = (&mut voice_l[..len], &mut voice_r[..len]);
+            block_l.fill(0.0);
+            block_r.fill(0.0);
+            for (index, voice) in self.voices.iter_mut().enumerate() {
+                if !voice.is_active() {
+                    continue;
+                }
+                voice_l.fill(0.0);
+                voice_r.fill(0.0);
+                voice.process_stereo(voice_l, voice_r);
+                tap(VoiceOutput {
+                    voice: index,
+                    note: voice.last_note(),
+                    released: voice.note().is_none(),
+                    left: voice_l,
+                    right: voice_r,
+                });
+                for (mix, sample) in block_l.iter_mut().zip(voice_l.iter()) {
+                    *mix += sample;
+                }
+                for (mix, sample) in block_r.iter_mut().zip(voice_r.iter()) {
+                    *mix += sample;
+                }
+            }
+            // At 1x the decimators pass the chunk through
+            let [decimator_l, decimator_r] = &mut self.decimators;
+            chunk_l.copy_from_slice(decimator_l.process(block_l));
+            chunk_r.copy_from_slice(decimator_r.process(block_r));
+        }
+    }
+
+    /// Start `note` (MIDI note number) at `velocity` (0.0 - 1.0). When all
+    /// voices allowed by the patch and the global limit are busy, one is
+    /// stolen according to the [`StealPolicy`], preferring voices already
+    /// released. With a glide time set, the note slides in from the
+    /// previous note's pitch. A non-finite velocity is ignored, like the
+    /// note.
+    pub fn note_on(&mut self, note: u8, velocity: f32) {
+        if !velocity.is_finite() {
+            return;
+        }
+        let limit = self.voice_limit();
+        let policy = self.steal_policy;
+        let pool = &mut self.voices[..limit];
+        let index = pool.iter()
+            .position(|voice| !voice.is_active())
+            .or_else(|| steal_voice(pool, policy, note));
+        
+        if let Some(index) = index {
+            self.note_count += 1;
+            let frequency = self.tuning.frequency(note);
+            pool[index].start(&self.params, note, frequency, velocity, self.note_count);
+            if let Some(previous) = self.last_frequency {
+                pool[index].glide_from(previous);
+            }
+            self.last_frequency = Some(frequency);
+        }
+    }
+
+    /// Release every voice holding `note`
+    pub fn note_off(&mut self, note: u8) {
+        for voice in &mut self.voices {
+            if voice.note() == Some(note) {
+                voice.release();
+            }
+        }
+    }
+
+    /// Release every held voice
+    pub fn all_notes_off(&mut self) {
+        for voice in &mut self.voices {
+            if voice.note().is_some() {
+                voice.release();
+            }
+        }
+    }
+
+    /// Bend every voice, `amount` from -1.0 (down) to 1.0 (up) covering the
+    /// patch's bend range. Voices glide to the new pitch rather than
+    /// jumping. Non-finite amounts are ignored.
+    pub fn pitch_bend(&mut self, amount: f32) {
+        if !amount.is_finite() {
+            return;
+        }
+        self.bend = amount.clamp(-1.0, 1.0);
+        let ratio = 2.0_f32.powf(self.bend * self.params.pitch_bend_range / 12.0);
+        for voice in &mut self.voices {
+            voice.set_bend(ratio);
+        }
+    }
+
+    /// Set the mod wheel, `amount` from 0.0 to 1.0, which raises the
+    /// modulation index by up to the patch's wheel depth. Non-finite
+    /// amounts are ignored.
+    pub fn mod_wheel(&mut self, amount: f32) {
+        if !amount.is_finite() {
+            return;
+        }
+        self.wheel = amount.clamp(0.0, 1.0);
+        let scale = 1.0 + self.wheel * self.params.mod_wheel_depth;
+        for voice in &mut self.voices {
+            voice.set_wheel(scale);
+        }
+    }
+
+    /// Respond to a MIDI message
+    pub fn handle_midi(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOn { note, velocity, .. } => {
+                self.note_on(note, velocity as f32 / 127.0);
+            }
+            MidiMessage::NoteOff { note, .. } => self.note_off(note),
+            MidiMessage::PitchBend { value, .. } => self.pitch_bend(bend_amount(value)),
+            MidiMessage::ControlChange { controller: CC_MOD_WHEEL, value, .. } => {
+                self.mod_wheel(value as f32 / 127.0);
+            }
+            MidiMessage::ControlChange { .. } => {}
+        }
+    }
+
+    /// Carry out a command queued by a [`Controller`](crate::Controller),
+    /// returning whatever heap data it displaced, so a caller on the audio
+    /// thread can hand that back to be freed elsewhere
+    pub fn apply(&mut self, command: Command) -> Option<Retired> {
+        match command {
+            Command::NoteOn { note, velocity } => self.note_on(note, velocity),
+            Command::NoteOff { note } => self.note_off(note),
+            Command::AllNotesOff => self.all_notes_off(),
+            Command::PitchBend(amount) => self.pitch_bend(amount),
+            Command::ModWheel(amount) => self.mod_wheel(amount),
+            Command::Midi(message) => self.handle_midi(message),
+            // The controller validated these before sending, so the audio
+            // thread skips checking them again
+            Command::SetParams(params) => return Some(Retired::Params(self.install(params, false))),
+            Command::SwitchParams(params) => return Some(Retired::Params(self.install(params, true))),
+            Command::SetTuning(tuning) => {
+                return Some(Retired::Tuning(std::mem::replace(&mut self.tuning, tuning)));
+            }
+        }
+        None
+    }
+
+    pub fn params(&self) -> &FMParams {
+        &self.params
+    }
+
+    /// Apply new parameters to the patch and every voice, leaving the
+    /// current ones in place if any value is out of range. Modulation
+    /// index, amplitude and operator ratios, levels and feedback ramp to
+    /// their new values over one block of the length last rendered, kept
+    /// between 5 and 20 ms, so live changes don't click.
+    pub fn set_params(&mut self, params: FMParams) -> Result<(), ParamError> {
+        params.validate()?;
+        self.install(Arc::new(params), false);
+        Ok(())
+    }
+
+    /// Switch to another patch, as on a program change. Sounding voices
+    /// crossfade from the old patch to the new one over the
+    /// [`switch_time`](Self::switch_time), each playing both meanwhile;
+    /// with no switch time this is [`set_params`](Self::set_params).
+    pub fn switch_params(&mut self, params: FMParams) -> Result<(), ParamError> {
+        params.validate()?;
+        self.install(Arc::new(params), true);
+        Ok(())
+    }
+
+    /// Crossfade time in seconds of [`switch_params`](Self::switch_params)
+    pub fn switch_time(&self) -> f32 {
+        self.switch_time
+    }
+
+    pub fn set_switch_time(&mut self, seconds: f32) {
+        self.switch_time = seconds.max(0.0);
+    }
+
+    /// Switch tuning system. Sounding notes keep their pitch; the new
+    /// tuning applies from the next note-on.
+    pub fn set_tuning(&mut self, tuning: impl Tuning + 'static) {
+        self.tuning = Box::new(tuning);
+    }
+
+    /// Global voice limit, shared by every patch
+    pub fn max_voices(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// Resize the voice pool. This allocates, so call it from the control
+    /// thread rather than while rendering.
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        let max_voices = max_voices.max(1);
+        let (sample_rate, params) = (self.voice_rate(), &self.params);
+        self.voices.resize_with(max_voices, || Voice::new(sample_rate, params));
+        self.pitch_bend(self.bend);
+        self.mod_wheel(self.wheel);
+    }
+
+    /// How many times the output rate voices render at; 1 unless set
+    pub fn oversampling(&self) -> usize {
+        self.decimators[0].factor()
+    }
+
+    /// Render voices at `factor` times the output rate (1, 2 or 4, rounded
+    /// up), filtering back down before output, so high modulation indices
+    /// alias less at the cost of that many times the CPU. This rebuilds
+    /// the voice pool, silencing it, so call it from the control thread
+    /// rather than while rendering.
+    pub fn set_oversampling(&mut self, factor: usize) {
+        self.decimators = [Decimator::new(factor), Decimator::new(factor)];
+        let (sample_rate, params) = (self.voice_rate(), &self.params);
+        for voice in &mut self.voices {
+            *voice = Voice::new(sample_rate, params);
+        }
+        self.pitch_bend(self.bend);
+        self.mod_wheel(self.wheel);
+    }
+
+    /// Run every voice in the pool through a few discarded blocks, then
+    /// silence them, so the first real notes don't pay for cold caches and
+    /// lazily mapped memory. Call before the audio stream starts, after
+    /// [`set_max_voices`](Self::set_max_voices).
+    pub fn warm_up(&mut self) {
+        let mut block = [0.0; WARM_UP_BLOCK_LEN];
+        for voice in &mut self.voices {
+            voice.start(&self.params, 69, self.tuning.frequency(69), 1.0, 0);
+            for _ in 0..WARM_UP_BLOCKS {
+                block.fill(0.0);
+                voice.process(&mut block);
+            }
+            voice.reset();
+        }
+    }
+
+    /// Silence the oldest sounding voice at once, skipping its release, to
+    /// save rendering time. Returns false if no voice was sounding.
+    pub fn drop_voice(&mut self) -> bool {
+        let oldest = self.voices.iter_mut()
+            .filter(|voice| voice.is_active())
+            .min_by_key(|voice| voice.started());
+        match oldest {
+            Some(voice) => {
+                voice.reset();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Voices currently sounding, including release tails
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|voice| voice.is_active()).count()
+    }
+
+    pub fn steal_policy(&self) -> StealPolicy {
+        self.steal_policy
+    }
+
+    pub fn set_steal_policy(&mut self, policy: StealPolicy) {
+        self.steal_policy = policy;
+    }
+
+    /// Swap in already validated parameters, crossfading sounding voices
+    /// if `switch` and there is a switch time, and return the patch they
+    /// replace. Idle voices take up the patch at their next note-on, so
+    /// only sounding voices are touched here.
+    fn install(&mut self, params: Arc<FMParams>, switch: bool) -> Arc<FMParams> {
+        let rate = self.voice_rate();
+        let fade = if switch { (self.switch_time * rate) as usize } else { 0 };
+        let ramp = (self.block_len * self.oversampling()).clamp(
+            (MIN_RAMP_SECONDS * rate) as usize,
+            (MAX_RAMP_SECONDS * rate) as usize,
+        );
+        for voice in self.voices.iter_mut().filter(|voice| voice.is_active()) {
+            if fade > 0 {
+                voice.crossfade_params(&params, fade);
+            } else {
+                voice.set_params(&params, ramp);
+            }
+        }
+        let old = std::mem::replace(&mut self.params, params);
+        self.release_excess_voices();
+        self.pitch_bend(self.bend);
+        self.mod_wheel(self.wheel);
+        old
+    }
+
+    /// Sample rate voices render at, including oversampling
+    fn voice_rate(&self) -> f32 {
+        self.sample_rate * self.oversampling() as f32
+    }
+
+    /// Voices this patch may use: its own polyphony within the global limit
+    fn voice_limit(&self) -> usize {
+        self.params.polyphony.clamp(1, self.voices.len())
+    }
+
+    /// Release voices a lowered limit no longer allows
+    fn release_excess_voices(&mut self) {
+        let limit = self.voice_limit();
+        for voice in &mut self.voices[limit..] {
+            voice.release();
+        }
+    }
+}
+
+/// Choose a busy voice in `pool` to take over for `note`, if `policy`
+/// allows stealing
+fn steal_voice(pool: &[Voice], policy: StealPolicy, note: u8) -> Option<usize> {
+    // Released voices are on their way out, so take one of those if any
+    let released = pool.iter().any(|voice| voice.note().is_none());
+    let candidates = pool.iter()
+        .enumerate()
+        .filter(|(_, voice)| !released || voice.note().is_none());
+    let oldest = || candidates.clone().min_by_key(|(_, voice)| voice.started());
+    
+    let chosen = match policy {
+        StealPolicy::Oldest => oldest(),
+        StealPolicy::Quietest => candidates.clone()
+            .min_by(|(_, a), (_, b)| a.level().total_cmp(&b.level())),
+        StealPolicy::SameNote => pool.iter()
+            .enumerate()
+            .find(|(_, voice)| voice.last_note() == note)
+            .or_else(oldest),
+        StealPolicy::None => None,
+    };
+    chosen.map(|(index, _)| index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note_to_freq;
+
+    fn render(synth: &mut FMSynth, frames: usize) -> Vec<f32> {
+        let (mut left, mut right) = (vec![0.0; frames], vec![0.0; frames]);
+        synth.process_stereo(&mut left, &mut right);
+        left
+    }
+
+    /// Three sounding voices: notes 60, 62 and 64, started second, third
+    /// and first, with 62 the quietest
+    fn pool() -> Vec<Voice> {
+        let params = FMParams { velocity_amp: 1.0, ..FMParams::default() };
+        let mut pool: Vec<Voice> = (0..3).map(|_| Voice::new(48000.0, &params)).collect();
+        let notes = [(60, 2, 0.9), (62, 3, 0.3), (64, 1, 0.6)];
+        for (voice, (note, started, velocity)) in pool.iter_mut().zip(notes) {
+            voice.start(&params, note, note_to_freq(note), velocity, started);
+            let (mut left, mut right) = ([0.0; 480], [0.0; 480]);
+            voice.process_stereo(&mut left, &mut right);
+        }
+        pool
+    }
+
+    #[test]
+    fn steal_policies_pick_their_voice() {
+        let pool = pool();
+        assert_eq!(steal_voice(&pool, StealPolicy::Oldest, 70), Some(2));
+        assert_eq!(steal_voice(&pool, StealPolicy::Quietest, 70), Some(1));
+        assert_eq!(steal_voice(&pool, StealPolicy::SameNote, 62), Some(1));
+        // With no voice on the note, the oldest goes
+        assert_eq!(steal_voice(&pool, StealPolicy::SameNote, 70), Some(2));
+        assert_eq!(steal_voice(&pool, StealPolicy::None, 70), None);
+    }
+
+    #[test]
+    fn released_voices_are_stolen_first() {
+        let mut pool = pool();
+        pool[0].release();
+        assert_eq!(steal_voice(&pool, StealPolicy::Oldest, 70), Some(0));
+        assert_eq!(steal_voice(&pool, StealPolicy::Quietest, 70), Some(0));
+        // The oldest of several released voices
+        pool[1].release();
+        assert_eq!(steal_voice(&pool, StealPolicy::Oldest, 70), Some(0));
+        assert_eq!(steal_voice(&pool, StealPolicy::None, 70), None);
+    }
+
+    #[test]
+    fn none_policy_drops_notes_when_full() {
+        let mut synth = FMSynth::new(48000.0, FMParams { polyphony: 2, ..FMParams::default() }).unwrap();
+        synth.set_steal_policy(StealPolicy::None);
+        for note in [60, 62, 64] {
+            synth.note_on(note, 1.0);
+        }
+        assert_eq!(synth.active_voices(), 2);
+        assert!(synth.voices.iter().all(|voice| voice.note() != Some(64)));
+    }
+
+    #[test]
+    fn non_finite_performance_input_is_ignored() {
+        let mut synth = FMSynth::new(48000.0, FMParams::default()).unwrap();
+        synth.note_on(60, 1.0);
+        synth.pitch_bend(f32::NAN);
+        synth.mod_wheel(f32::NAN);
+        synth.pitch_bend(f32::INFINITY);
+        synth.note_on(64, f32::NAN);
+        assert!(render(&mut synth, 4800).iter().all(|sample| sample.is_finite()));
+
+        synth.pitch_bend(0.0);
+        synth.note_on(67, 1.0);
+        let output = render(&mut synth, 4800);
+        assert!(output.iter().all(|sample| sample.is_finite()));
+        assert!(output.iter().any(|&sample| sample != 0.0));
+    }
+}
@@ -0,0 +1,110 @@
+//! Tuning systems mapping MIDI notes to frequencies
+
+/// Maps MIDI note numbers to frequencies
+pub trait Tuning: Send {
+    /// Frequency in Hz for `note`
+    fn frequency(&self, note: u8) -> f32;
+
+    /// This tuning as a keyed temperament, if it is one, so frontends can
+    /// show it
+    fn keyed(&self) -> Option<KeyedTuning> {
+        None
+    }
+}
+
+/// Built-in twelve-note temperaments
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Temperament {
+    Equal,
+    /// Five-limit just intonation
+    Just,
+    /// Quarter-comma meantone
+    Meantone,
+    Pythagorean,
+}
+
+impl Temperament {
+    pub const ALL: [Temperament; 4] = [
+        Temperament::Equal,
+        Temperament::Just,
+        Temperament::Meantone,
+        Temperament::Pythagorean,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Temperament::Equal => "equal",
+            Temperament::Just => "just",
+            Temperament::Meantone => "meantone",
+            Temperament::Pythagorean => "pythagorean",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Size of each scale degree above the root, in cents
+    fn cents(self) -> [f32; 12] {
+        match self {
+            Temperament::Equal => [
+                0.0, 100.0, 200.0, 300.0, 400.0, 500.0,
+                600.0, 700.0, 800.0, 900.0, 1000.0, 1100.0,
+            ],
+            // 1, 16/15, 9/8, 6/5, 5/4, 4/3, 45/32, 3/2, 8/5, 5/3, 9/5, 15/8
+            Temperament::Just => [
+                0.0, 111.73, 203.91, 315.64, 386.31, 498.04,
+                590.22, 701.96, 813.69, 884.36, 1017.60, 1088.27,
+            ],
+            // Fifths narrowed by a quarter syntonic comma
+            Temperament::Meantone => [
+                0.0, 76.05, 193.16, 310.26, 386.31, 503.42,
+                579.47, 696.58, 772.63, 889.74, 1006.84, 1082.89,
+            ],
+            // Stacked pure fifths
+            Temperament::Pythagorean => [
+                0.0, 90.22, 203.91, 294.13, 407.82, 498.04,
+                611.73, 701.96, 792.18, 905.87, 996.09, 1109.78,
+            ],
+        }
+    }
+}
+
+/// A temperament keyed to a root pitch class (0 = C ... 11 = B). The root
+/// keeps its equal-tempered pitch, so A4 stays at 440 Hz in A.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyedTuning {
+    pub temperament: Temperament,
+    pub root: u8,
+}
+
+impl KeyedTuning {
+    pub fn new(temperament: Temperament, root: u8) -> Self {
+        Self {
+            temperament,
+            root: root % 12,
+        }
+    }
+}
+
+impl Default for KeyedTuning {
+    fn default() -> Self {
+        Self::new(Temperament::Equal, 0)
+    }
+}
+
+impl Tuning for KeyedTuning {
+    fn frequency(&self, note: u8) -> f32 {
+        let degree = (note as i32 - self.root as i32).rem_euclid(12);
+        let tonic = note as i32 - degree;
+
+        // The tonic below this note is equal-tempered; the degree above it
+        // comes from the temperament
+        let tonic_freq = 440.0 * 2.0_f32.powf((tonic as f32 - 69.0) / 12.0);
+        tonic_freq * 2.0_f32.powf(self.temperament.cents()[degree as usize] / 1200.0)
+    }
+
+    fn keyed(&self) -> Option<KeyedTuning> {
+        Some(*self)
+    }
+}
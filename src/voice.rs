@@ -0,0 +1,434 @@
+//! A single sounding note: oscillator plus timbre envelope
+
+use crate::{Envelope, FMOscillator, FMParams, Lfo, LfoDestination};
+
+/// Time constant in seconds of the glide towards new pitch bend and mod
+/// wheel values, long enough to hide the steps between MIDI messages
+const CONTROL_SMOOTHING: f32 = 0.005;
+
+/// Samples a voice renders per pass in `process` and `process_stereo`
+const VOICE_BLOCK: usize = 64;
+
+/// One voice of the synth, playing one note at a time
+pub(crate) struct Voice {
+    oscillator: FMOscillator,
+    right: FMOscillator,       // Right-channel copy, used for stereo width
+    old: FMOscillator,         // Previous patch's oscillators, fading out
+    old_right: FMOscillator,   // after a crossfaded switch
+    fade: f32,                 // Previous patch's share of the output
+    fade_step: f32,
+    stereo_detune: f32,        // Right copy's frequency ratio to the left
+    stereo_phase: f32,         // Right copy's phase lead in cycles
+    index_envelope: Envelope,  // Shapes modulation index independently of loudness
+    index_env_amount: f32,
+    lfo: Lfo,
+    lfo_destination: LfoDestination,
+    bend: f32,                 // Pitch bend frequency ratio, gliding to bend_target
+    bend_target: f32,
+    wheel: f32,                // Mod wheel index scale, gliding to wheel_target
+    wheel_target: f32,
+    control_coeff: f32,        // Per-sample smoothing towards the targets
+    release_index: f32,        // Key-off index burst from the patch
+    release_decay: f32,        // Per-sample decay of the burst
+    release_bump: f32,         // Extra index scale left from the burst
+    glide_time: f32,           // Portamento time in seconds, from the patch
+    glide: f32,                // Octaves still to slide to the note's pitch
+    glide_step: f32,           // Octaves slid per sample
+    sample_rate: f32,
+    velocity_amp: f32,         // Velocity sensitivities from the patch
+    velocity_index: f32,
+    key_scale_break: f32,      // Key scaling from the patch, in dB per octave
+    key_scale_index: f32,
+    key_scale_level: f32,
+    note_gain: f32,            // Amplitude and index scales for this note's
+    note_index_scale: f32,     // velocity and key
+    note: Option<u8>,          // Key holding this voice, None once released
+    last_note: u8,             // Note last started, kept through the release
+    started: u64,              // Note-on order, for stealing the oldest voice
+}
+
+impl Voice {
+    pub(crate) fn new(sample_rate: f32, params: &FMParams) -> Self {
+        let mut voice = Self {
+            oscillator: FMOscillator::new(sample_rate, params.clone()),
+            right: FMOscillator::new(sample_rate, params.clone()),
+            old: FMOscillator::new(sample_rate, params.clone()),
+            old_right: FMOscillator::new(sample_rate, params.clone()),
+            fade: 0.0,
+            fade_step: 0.0,
+            stereo_detune: 1.0,
+            stereo_phase: 0.0,
+            index_envelope: Envelope::new(sample_rate),
+            index_env_amount: 0.0,
+            lfo: Lfo::new(sample_rate),
+            lfo_destination: LfoDestination::Pitch,
+            bend: 1.0,
+            bend_target: 1.0,
+            wheel: 1.0,
+            wheel_target: 1.0,
+            control_coeff: 1.0 - (-1.0 / (CONTROL_SMOOTHING * sample_rate)).exp(),
+            release_index: 0.0,
+            release_decay: 0.0,
+            release_bump: 0.0,
+            glide_time: 0.0,
+            glide: 0.0,
+            glide_step: 0.0,
+            sample_rate,
+            velocity_amp: 0.0,
+            velocity_index: 0.0,
+            key_scale_break: 60.0,
+            key_scale_index: 0.0,
+            key_scale_level: 0.0,
+            note_gain: 1.0,
+            note_index_scale: 1.0,
+            note: None,
+            last_note: 0,
+            started: 0,
+        };
+        voice.set_params(params, 0);
+        voice
+    }
+
+    /// Next sample mixed to mono
+    pub(crate) fn next_sample(&mut self) -> f32 {
+        let [left, right] = self.next_frame();
+        (left + right) * 0.5
+    }
+
+    /// Next left and right samples. Without stereo width both are the same.
+    pub(crate) fn next_frame(&mut self) -> [f32; 2] {
+        let [index_scale, pitch_scale, gain] = self.controls();
+        let mut left = self.oscillator.next_sample(index_scale, pitch_scale) * gain;
+        let fade = self.fade;
+        if fade > 0.0 {
+            let old = self.old.next_sample(index_scale, pitch_scale) * gain;
+            left += (old - left) * fade;
+            self.fade = (fade - self.fade_step).max(0.0);
+        }
+        if !self.is_stereo() {
+            return [left, left];
+        }
+        let mut right = self.right.next_sample(index_scale, pitch_scale * self.stereo_detune) * gain;
+        if fade > 0.0 {
+            let old = self.old_right.next_sample(index_scale, pitch_scale * self.stereo_detune) * gain;
+            right += (old - right) * fade;
+        }
+        [left, right]
+    }
+
+    /// Add a block of this voice into `output`, stopping early once its
+    /// release ends
+    pub(crate) fn process(&mut self, output: &mut [f32]) {
+        for chunk in output.chunks_mut(VOICE_BLOCK) {
+            if !self.is_active() {
+                break;
+            }
+            let mut left = [0.0; VOICE_BLOCK];
+            let mut right = [0.0; VOICE_BLOCK];
+            let len = chunk.len();
+            self.render_block(&mut left[..len], &mut right[..len]);
+            for ((sample, l), r) in chunk.iter_mut().zip(&left).zip(&right) {
+                *sample += (l + r) * 0.5;
+            }
+        }
+    }
+
+    /// Add a stereo block of this voice into `left` and `right`
+    pub(crate) fn process_stereo(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let chunks = left.chunks_mut(VOICE_BLOCK).zip(right.chunks_mut(VOICE_BLOCK));
+        for (chunk_l, chunk_r) in chunks {
+            if !self.is_active() {
+                break;
+            }
+            let mut block_l = [0.0; VOICE_BLOCK];
+            let mut block_r = [0.0; VOICE_BLOCK];
+            let len = chunk_l.len();
+            self.render_block(&mut block_l[..len], &mut block_r[..len]);
+            for (out, sample) in chunk_l.iter_mut().zip(&block_l).chain(chunk_r.iter_mut().zip(&block_r)) {
+                *out += sample;
+            }
+        }
+    }
+
+    /// Start playing `note` of `patch` at `frequency` Hz. Idle voices
+    /// aren't told about patch changes, so the note takes up the patch here.
+    pub(crate) fn start(
+        &mut self,
+        patch: &FMParams,
+        note: u8,
+        frequency: f32,
+        velocity: f32,
+        started: u64,
+    ) {
+        self.apply_patch(patch);
+        let mut params = patch.for_note(note);
+        params.retune(frequency);
+        self.oscillator.set_params(params.clone());
+        self.right.set_params(params);
+        
+        self.note = Some(note);
+        self.last_note = note;
+        self.started = started;
+        // NaN would survive clamping and silence the voice for good
+        let velocity = if velocity.is_finite() { velocity.clamp(0.0, 1.0) } else { 0.0 };
+        let octaves = (note as f32 - self.key_scale_break).max(0.0) / 12.0;
+        self.note_gain = (1.0 - self.velocity_amp * (1.0 - velocity))
+            * 10.0_f32.powf(-self.key_scale_level * octaves / 20.0);
+        self.note_index_scale = (1.0 - self.velocity_index * (1.0 - velocity))
+            * 10.0_f32.powf(-self.key_scale_index * octaves / 20.0);
+        self.oscillator.retrigger(velocity);
+        self.right.retrigger(velocity);
+        self.right.sync_phases(&self.oscillator, self.stereo_phase);
+        self.index_envelope.trigger();
+        self.lfo.reset();
+        self.glide = 0.0;
+        self.release_bump = 0.0;
+        self.fade = 0.0;
+    }
+
+    /// Slide into the current note from `frequency` Hz over the patch's
+    /// glide time. Call after [`start`](Self::start).
+    pub(crate) fn glide_from(&mut self, frequency: f32) {
+        let samples = self.glide_time * self.sample_rate;
+        if samples >= 1.0 && frequency > 0.0 {
+            self.glide = (frequency / self.oscillator.params().frequency).log2();
+            self.glide_step = self.glide / samples;
+        }
+    }
+
+    /// Glide to a pitch bend given as a frequency ratio
+    pub(crate) fn set_bend(&mut self, ratio: f32) {
+        self.bend_target = ratio;
+    }
+
+    /// Glide to a mod wheel modulation index scale
+    pub(crate) fn set_wheel(&mut self, scale: f32) {
+        self.wheel_target = scale;
+    }
+
+    /// Start the release, with the patch's key-off index burst
+    pub(crate) fn release(&mut self) {
+        if self.note.is_some() {
+            self.release_bump = self.release_index;
+        }
+        self.note = None;
+        self.oscillator.release();
+        self.right.release();
+        self.old.release();
+        self.old_right.release();
+        self.index_envelope.release();
+    }
+
+    /// Stop at once, skipping the release tail
+    pub(crate) fn reset(&mut self) {
+        self.note = None;
+        self.release_bump = 0.0;
+        self.fade = 0.0;
+        self.oscillator.reset();
+        self.right.reset();
+        self.index_envelope.reset();
+    }
+
+    /// True while the voice is sounding, including its release tail
+    pub(crate) fn is_active(&self) -> bool {
+        self.oscillator.is_active()
+    }
+
+    /// Rough loudness of the voice right now, for stealing the quietest
+    pub(crate) fn level(&self) -> f32 {
+        self.oscillator.level() * self.note_gain
+    }
+
+    /// Note this voice last started, held or not
+    pub(crate) fn last_note(&self) -> u8 {
+        self.last_note
+    }
+
+    pub(crate) fn note(&self) -> Option<u8> {
+        self.note
+    }
+
+    pub(crate) fn started(&self) -> u64 {
+        self.started
+    }
+
+    /// Apply patch parameters, keeping the voice's own pitch, with
+    /// continuous values ramping over `ramp_samples`
+    pub(crate) fn set_params(&mut self, params: &FMParams, ramp_samples: usize) {
+        self.apply_patch(params);
+        let frequency = self.oscillator.params().frequency;
+        let mut params = params.for_note(self.last_note);
+        params.frequency = frequency;
+        self.oscillator.ramp_params(params.clone(), ramp_samples);
+        self.right.ramp_params(params, ramp_samples);
+    }
+
+    /// Take up the voice-wide settings of the patch; the oscillators are
+    /// set separately
+    fn apply_patch(&mut self, params: &FMParams) {
+        self.index_envelope.set_adsr(
+            params.index_attack,
+            params.index_decay,
+            params.index_sustain,
+            params.index_release,
+        );
+        self.index_env_amount = params.index_env_amount;
+        self.velocity_amp = params.velocity_amp;
+        self.velocity_index = params.velocity_index;
+        self.key_scale_break = params.key_scale_break;
+        self.key_scale_index = params.key_scale_index;
+        self.key_scale_level = params.key_scale_level;
+        self.release_index = params.release_index;
+        self.release_decay = (-1.0 / (params.release_index_time * self.sample_rate)).exp();
+        self.lfo.set(params.lfo_rate, params.lfo_depth);
+        self.lfo_destination = params.lfo_destination;
+        self.glide_time = params.glide_time;
+        self.stereo_detune = 2.0_f32.powf(params.stereo_detune / 1200.0);
+        self.stereo_phase = params.stereo_phase / 360.0;
+    }
+
+    /// Switch to a new patch like [`set_params`](Self::set_params), but if
+    /// the voice is sounding keep playing the old one alongside, fading
+    /// from it to the new one over `fade_samples`
+    pub(crate) fn crossfade_params(&mut self, params: &FMParams, fade_samples: usize) {
+        if self.is_active() && fade_samples > 0 {
+            self.old.clone_from(&self.oscillator);
+            self.old_right.clone_from(&self.right);
+            self.fade = 1.0;
+            self.fade_step = 1.0 / fade_samples as f32;
+        }
+        self.set_params(params, 0);
+    }
+
+    /// Advance the smoothed controls, timbre envelope, LFO and glide by one
+    /// sample, giving the oscillators' index and pitch scales and the gain
+    fn controls(&mut self) -> [f32; 3] {
+        // Blend between the static index and the enveloped index
+        let index_env = self.index_envelope.process();
+        self.bend += (self.bend_target - self.bend) * self.control_coeff;
+        self.wheel += (self.wheel_target - self.wheel) * self.control_coeff;
+        let mut index_scale = (1.0 - self.index_env_amount + self.index_env_amount * index_env)
+            * self.wheel * self.note_index_scale * (1.0 + self.release_bump);
+        self.release_bump *= self.release_decay;
+        
+        let lfo = self.lfo.process();
+        let mut pitch_scale = self.bend;
+        if self.glide != 0.0 {
+            pitch_scale *= self.glide.exp2();
+            self.glide = if self.glide.abs() <= self.glide_step.abs() {
+                0.0
+            } else {
+                self.glide - self.glide_step
+            };
+        }
+        let mut gain = self.note_gain;
+        match self.lfo_destination {
+            LfoDestination::Pitch if lfo != 0.0 => pitch_scale *= 2.0_f32.powf(lfo / 12.0),
+            LfoDestination::Pitch => {}
+            LfoDestination::Amplitude => gain *= 1.0 - 0.5 * (self.lfo.depth() - lfo),
+            LfoDestination::ModIndex => index_scale *= 1.0 + lfo,
+        }
+        [index_scale, pitch_scale, gain]
+    }
+
+    /// Fill `left` and `right` (up to `VOICE_BLOCK` samples) with the same
+    /// frames as [`next_frame`](Self::next_frame), rendering the
+    /// oscillators a block at a time
+    fn render_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        if self.fade > 0.0 {
+            for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                [*l, *r] = self.next_frame();
+            }
+            return;
+        }
+        
+        let len = left.len();
+        let mut index_scale = [0.0; VOICE_BLOCK];
+        let mut pitch_scale = [0.0; VOICE_BLOCK];
+        let mut gain = [0.0; VOICE_BLOCK];
+        for n in 0..len {
+            [index_scale[n], pitch_scale[n], gain[n]] = self.controls();
+        }
+        self.oscillator.process_block(&index_scale[..len], &pitch_scale[..len], left);
+        if self.is_stereo() {
+            for pitch in &mut pitch_scale[..len] {
+                *pitch *= self.stereo_detune;
+            }
+            self.right.process_block(&index_scale[..len], &pitch_scale[..len], right);
+        } else {
+            right.copy_from_slice(left);
+        }
+        for ((l, r), &gain) in left.iter_mut().zip(right.iter_mut()).zip(&gain) {
+            *l *= gain;
+            *r *= gain;
+        }
+    }
+
+    /// True if the right channel differs from the left
+    fn is_stereo(&self) -> bool {
+        self.stereo_detune != 1.0 || self.stereo_phase != 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ModulationInput;
+
+    /// A patch using feedback and exponential FM, on a 4>3>2>1 stack
+    fn patch() -> FMParams {
+        let mut params = FMParams { algorithm: 1, modulation_index: 2.0, ..FMParams::default() };
+        for (i, op) in params.operators.iter_mut().enumerate() {
+            op.level = 0.8;
+            op.ratio = [1.0, 2.0, 3.5, 1.0][i];
+        }
+        params.operators[3].feedback = 3.0;
+        params.operators[2].modulation_input = ModulationInput::Exponential;
+        params
+    }
+
+    /// Render `samples` frames sample by sample, or in blocks of
+    /// `block` (split at odd lengths), with a ramped patch change `at`
+    fn render(block: Option<usize>, samples: usize, at: usize) -> Vec<[f32; 2]> {
+        let mut voice = Voice::new(48000.0, &patch());
+        voice.start(&patch(), 57, 220.0, 0.9, 1);
+        let mut changed = patch();
+        changed.modulation_index = 5.0;
+        changed.operators[1].ratio = 3.0;
+        changed.operators[3].feedback = 6.0;
+
+        let mut frames = Vec::with_capacity(samples);
+        while frames.len() < samples {
+            if frames.len() == at {
+                voice.set_params(&changed, 300);
+            }
+            let end = if frames.len() < at { at } else { samples };
+            match block {
+                None => frames.push(voice.next_frame()),
+                Some(block) => {
+                    let len = block.min(end - frames.len());
+                    let (mut left, mut right) = (vec![0.0; len], vec![0.0; len]);
+                    voice.process_stereo(&mut left, &mut right);
+                    frames.extend(left.into_iter().zip(right).map(|(l, r)| [l, r]));
+                }
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn blocks_match_sample_by_sample() {
+        // The change lands mid-block and its ramp spans several blocks
+        let expected = render(None, 2000, 1000);
+        for block in [VOICE_BLOCK, 37, 200] {
+            let blocks = render(Some(block), 2000, 1000);
+            for (n, (a, b)) in expected.iter().zip(&blocks).enumerate() {
+                assert!(
+                    (a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4,
+                    "blocks of {} differ at sample {}: {:?} and {:?}", block, n, a, b,
+                );
+            }
+        }
+        assert!(expected.iter().any(|frame| frame[0].abs() > 0.01));
+    }
+}
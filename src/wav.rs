@@ -0,0 +1,18 @@
+//! WAV file output
+
+use std::path::Path;
+
+/// Write mono samples to a 16-bit PCM WAV file, clipping to -1.0 - 1.0
+pub fn write_wav(path: impl AsRef<Path>, samples: &[f32], sample_rate: u32) -> hound::Result<()> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()
+}